@@ -0,0 +1,296 @@
+//! Importer for sprite sheets exported from Aseprite as JSON + PNG. Both the
+//! hash and array frame layouts are understood, but the array layout keeps
+//! frame order without relying on frame names.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use rustc_serialize::json::Json;
+
+use ::assets::{load_image_with, ImportOptions};
+use ::gfx::image::{Image, SubImage};
+use ::gfx::animation::{Animation, Clip, Frame, PlayMode};
+use ::math::Position;
+use ::math::rect::Rect;
+
+/// Length of a GB frame in milliseconds.
+const FRAME_MS: f64 = 1000.0 / 59.73;
+
+pub struct AsepriteSheet {
+    pub image: Rc<Image>,
+    /// Frames in order, offset into the untrimmed sprite for trimmed exports.
+    pub frames: Vec<Frame>,
+    /// One clip per frame tag, or a single "default" clip without tags.
+    pub clips: HashMap<String, Clip>,
+    /// Clip names in the order the tags appear.
+    pub clip_names: Vec<String>,
+    /// Bounds of each slice on its first key.
+    pub slices: HashMap<String, Rect>
+}
+
+impl AsepriteSheet {
+    /// An animation with every clip, starting on the first tag.
+    pub fn animation(&self) -> Animation {
+        let mut animation = Animation::new();
+        for name in self.clip_names.iter() {
+            if let Some(clip) = self.clips.get(name) {
+                animation.add_clip(name, clip.clone());
+            }
+        }
+        animation
+    }
+}
+
+/// Convert a duration in milliseconds to whole game frames, at least one.
+fn ms_to_frames(ms: f64) -> u32 {
+    let frames = (ms / FRAME_MS).round();
+    if frames < 1.0 { 1 } else { frames as u32 }
+}
+
+fn json_i32(j: &Json, key: &str) -> i32 {
+    j.find(key).and_then(|v| v.as_i64()).unwrap_or(0) as i32
+}
+
+fn json_rect(j: &Json) -> Rect {
+    Rect::new(json_i32(j, "x"), json_i32(j, "y"), json_i32(j, "w") as u32, json_i32(j, "h") as u32)
+}
+
+/// The last run of digits in a frame name, which Aseprite uses for the
+/// frame number.
+fn frame_number(name: &str) -> Option<u32> {
+    let digits: String = name.chars().rev()
+        .skip_while(|c| !c.is_ascii_digit())
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    digits.chars().rev().collect::<String>().parse().ok()
+}
+
+/// Load an Aseprite JSON export. The sheet image named in its metadata is
+/// loaded relative to the JSON file and quantized like `load_image`.
+pub fn load_aseprite(path: PathBuf) -> Result<AsepriteSheet, String> {
+    load_aseprite_with(path, &ImportOptions::new())
+}
+
+/// Load an Aseprite JSON export, quantizing the sheet with `options`.
+pub fn load_aseprite_with(path: PathBuf, options: &ImportOptions) -> Result<AsepriteSheet, String> {
+    let mut s = String::new();
+    let mut file = try!(File::open(&path).map_err(|e| format!("{}: {}", path.display(), e)));
+    try!(file.read_to_string(&mut s).map_err(|e| format!("{}: {}", path.display(), e)));
+
+    let dir = path.parent().unwrap_or(Path::new(""));
+    parse_aseprite(&s, |name| load_image_with(dir.join(name), options))
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Read an Aseprite JSON export. `load_image` is given the sheet image's
+/// name from the metadata.
+pub fn parse_aseprite<F>(text: &str, load_image: F) -> Result<AsepriteSheet, String>
+    where F: FnOnce(&str) -> Result<Image, String> {
+    let j = try!(Json::from_str(text).map_err(|e| e.to_string()));
+
+    let meta = try!(j.find("meta").ok_or("Missing meta".to_string()));
+    let image_name = try!(meta.find("image").and_then(|i| i.as_string()).ok_or("Missing meta.image".to_string()));
+    let image = Rc::new(try!(load_image(image_name)));
+
+    // Frames are an array or an object keyed by name. Object keys come back
+    // sorted, so order those by frame number instead.
+    let raw: Vec<&Json> = match j.find("frames") {
+        Some(&Json::Array(ref a)) => a.iter().collect(),
+        Some(&Json::Object(ref o)) => {
+            let mut named: Vec<(&String, &Json)> = o.iter().collect();
+            named.sort_by_key(|a| frame_number(a.0));
+            named.into_iter().map(|(_, f)| f).collect()
+        },
+        _ => return Err("Missing frames".to_string())
+    };
+
+    let mut frames = Vec::with_capacity(raw.len());
+    for f in raw.into_iter() {
+        if f.find("rotated").and_then(|r| r.as_boolean()).unwrap_or(false) {
+            return Err("Rotated frames are not supported".to_string());
+        }
+        let rect = json_rect(try!(f.find("frame").ok_or("Frame without bounds".to_string())));
+        let offset = match f.find("spriteSourceSize") {
+            Some(s) => Position::new(json_i32(s, "x"), json_i32(s, "y")),
+            None => Position::new(0, 0)
+        };
+        let duration = f.find("duration").and_then(|d| d.as_f64()).unwrap_or(100.0);
+
+        frames.push(Frame {
+            image: SubImage::new(image.clone(), rect),
+            duration: ms_to_frames(duration),
+            offset: offset
+        });
+    }
+
+    let mut clips = HashMap::new();
+    let mut clip_names = Vec::new();
+    if let Some(tags) = meta.find("frameTags").and_then(|t| t.as_array()) {
+        for tag in tags.iter() {
+            let name = tag.find("name").and_then(|n| n.as_string()).unwrap_or("").to_string();
+            let from = json_i32(tag, "from") as usize;
+            let to = json_i32(tag, "to") as usize;
+            if from > to || to >= frames.len() {
+                return Err(format!("Tag \"{}\" has frames out of range", name));
+            }
+
+            let mut clip_frames: Vec<Frame> = frames[from..to + 1].to_vec();
+            let direction = tag.find("direction").and_then(|d| d.as_string()).unwrap_or("forward");
+            if direction == "reverse" || direction == "pingpong_reverse" {
+                clip_frames.reverse();
+            }
+            let repeat = tag.find("repeat").and_then(|r| r.as_string()).unwrap_or("0");
+            let mode = if direction.starts_with("pingpong") {
+                PlayMode::PingPong
+            } else if repeat == "1" {
+                PlayMode::Once
+            } else {
+                PlayMode::Loop
+            };
+
+            clips.insert(name.clone(), Clip::new(clip_frames, mode));
+            clip_names.push(name);
+        }
+    }
+    if clip_names.is_empty() {
+        clips.insert("default".to_string(), Clip::new(frames.clone(), PlayMode::Loop));
+        clip_names.push("default".to_string());
+    }
+
+    let mut slices = HashMap::new();
+    if let Some(list) = meta.find("slices").and_then(|s| s.as_array()) {
+        for slice in list.iter() {
+            let name = slice.find("name").and_then(|n| n.as_string()).unwrap_or("").to_string();
+            let bounds = slice.find("keys")
+                .and_then(|k| k.as_array())
+                .and_then(|k| k.first())
+                .and_then(|k| k.find("bounds"));
+            if let Some(b) = bounds {
+                slices.insert(name, json_rect(b));
+            }
+        }
+    }
+
+    Ok(AsepriteSheet {
+        image: image,
+        frames: frames,
+        clips: clips,
+        clip_names: clip_names,
+        slices: slices
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_aseprite;
+    use ::gfx::image::Image;
+    use ::gfx::animation::PlayMode;
+    use ::math::Position;
+    use ::math::rect::Rect;
+
+    fn sheet() -> Result<Image, String> {
+        Ok(Image::new((64, 16), 0u8))
+    }
+
+    const ARRAY: &'static str = r#"{
+        "frames": [
+            {"filename": "hero 0.aseprite", "frame": {"x": 0, "y": 0, "w": 16, "h": 16},
+             "spriteSourceSize": {"x": 0, "y": 0, "w": 16, "h": 16}, "duration": 100},
+            {"filename": "hero 1.aseprite", "frame": {"x": 16, "y": 0, "w": 12, "h": 14},
+             "spriteSourceSize": {"x": 2, "y": 1, "w": 12, "h": 14}, "duration": 50},
+            {"filename": "hero 2.aseprite", "frame": {"x": 28, "y": 0, "w": 16, "h": 16}, "duration": 1},
+            {"filename": "hero 3.aseprite", "frame": {"x": 44, "y": 0, "w": 16, "h": 16}}
+        ],
+        "meta": {
+            "image": "hero.png",
+            "frameTags": [
+                {"name": "walk", "from": 0, "to": 1, "direction": "forward"},
+                {"name": "back", "from": 1, "to": 3, "direction": "reverse", "repeat": "1"},
+                {"name": "bob", "from": 2, "to": 3, "direction": "pingpong"}
+            ],
+            "slices": [
+                {"name": "hitbox", "keys": [{"frame": 0, "bounds": {"x": 2, "y": 4, "w": 12, "h": 10}},
+                                            {"frame": 2, "bounds": {"x": 0, "y": 0, "w": 1, "h": 1}}]},
+                {"name": "empty", "keys": []}
+            ]
+        }
+    }"#;
+
+    #[test]
+    fn array_layout() {
+        let mut name = String::new();
+        let s = parse_aseprite(ARRAY, |n| { name = n.to_string(); sheet() }).unwrap();
+        assert_eq!(name, "hero.png");
+
+        let rects: Vec<Rect> = s.frames.iter().map(|f| f.image.rect()).collect();
+        assert_eq!(rects[1], Rect::new(16, 0, 12, 14));
+        assert_eq!(s.frames[1].offset, Position::new(2, 1));
+        assert_eq!(s.frames[2].offset, Position::new(0, 0));
+        let durations: Vec<u32> = s.frames.iter().map(|f| f.duration).collect();
+        assert_eq!(durations, vec![6, 3, 1, 6]);
+    }
+
+    #[test]
+    fn tags_become_clips() {
+        let s = parse_aseprite(ARRAY, |_| sheet()).unwrap();
+        assert_eq!(s.clip_names, vec!["walk", "back", "bob"]);
+
+        let walk = &s.clips["walk"];
+        assert_eq!((walk.frames.len(), walk.mode), (2, PlayMode::Loop));
+        let back = &s.clips["back"];
+        let xs: Vec<i32> = back.frames.iter().map(|f| f.image.rect().x()).collect();
+        assert_eq!((xs, back.mode), (vec![44, 28, 16], PlayMode::Once));
+        assert_eq!(back.frames[2].offset, Position::new(2, 1));
+        assert_eq!(s.clips["bob"].mode, PlayMode::PingPong);
+
+        assert_eq!(s.animation().current_clip(), "walk");
+    }
+
+    #[test]
+    fn slices_use_their_first_key() {
+        let s = parse_aseprite(ARRAY, |_| sheet()).unwrap();
+        assert_eq!(s.slices.get("hitbox"), Some(&Rect::new(2, 4, 12, 10)));
+        assert!(!s.slices.contains_key("empty"));
+    }
+
+    #[test]
+    fn hash_layout_orders_by_frame_number() {
+        let text = r#"{
+            "frames": {
+                "hero 10.aseprite": {"frame": {"x": 10, "y": 0, "w": 1, "h": 1}},
+                "hero 2.aseprite": {"frame": {"x": 2, "y": 0, "w": 1, "h": 1},
+                                    "spriteSourceSize": {"x": 3, "y": 4, "w": 1, "h": 1}},
+                "hero 9.aseprite": {"frame": {"x": 9, "y": 0, "w": 1, "h": 1}}
+            },
+            "meta": {"image": "hero.png"}
+        }"#;
+        let s = parse_aseprite(text, |_| sheet()).unwrap();
+        let xs: Vec<i32> = s.frames.iter().map(|f| f.image.rect().x()).collect();
+        assert_eq!(xs, vec![2, 9, 10]);
+        assert_eq!(s.frames[0].offset, Position::new(3, 4));
+        assert_eq!(s.clip_names, vec!["default"]);
+        assert_eq!(s.clips["default"].frames.len(), 3);
+    }
+
+    #[test]
+    fn rejects_bad_sheets() {
+        let frame = r#"{"frame": {"x": 0, "y": 0, "w": 1, "h": 1}}"#;
+        let rotated = r#"{"frame": {"x": 0, "y": 0, "w": 1, "h": 1}, "rotated": true}"#;
+        let bad_tag = r#"[{"name": "t", "from": 1, "to": 2}]"#;
+        let cases = [
+            format!(r#"{{"frames": [{}]}}"#, frame),
+            r#"{"meta": {"image": "a.png"}}"#.to_string(),
+            format!(r#"{{"frames": [{}], "meta": {{"image": "a.png"}}}}"#, rotated),
+            format!(r#"{{"frames": [{}], "meta": {{"image": "a.png", "frameTags": {}}}}}"#, frame, bad_tag),
+            "{".to_string()
+        ];
+        for text in cases.iter() {
+            assert!(parse_aseprite(text, |_| sheet()).is_err(), "accepted {}", text);
+        }
+        let missing = format!(r#"{{"frames": [{}], "meta": {{"image": "a.png"}}}}"#, frame);
+        assert_eq!(parse_aseprite(&missing, |_| Err("gone".to_string())).err(), Some("gone".to_string()));
+    }
+}
@@ -0,0 +1,202 @@
+//! Raw Game Boy tile data, as used by `.2bpp` and `.1bpp` files.
+//!
+//! Tiles are 8x8 and stored one after another. In 2bpp each row is two
+//! bytes, the low bit plane then the high one, with the leftmost pixel in the
+//! top bit. 1bpp rows are a single byte. GB color 0 is the lightest, so it
+//! becomes shade 3 here and the other way around.
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+use ::gfx::image::Image;
+use ::gfx::tilemap::TILE_SIZE;
+
+#[inline]
+fn to_shade(color: u8) -> u8 {
+    3 - color
+}
+
+#[inline]
+fn to_color(shade: u8) -> u8 {
+    if shade > 3 { 0 } else { 3 - shade }
+}
+
+/// Size in pixels of a sheet of `count` tiles laid out `tiles_per_row` wide.
+fn sheet_size(count: usize, tiles_per_row: u32) -> Result<(u32, u32), String> {
+    if tiles_per_row == 0 {
+        return Err("Tiles per row must be at least 1".to_string());
+    }
+    let rows = (count as u32 + tiles_per_row - 1) / tiles_per_row;
+    Ok((tiles_per_row * TILE_SIZE, rows * TILE_SIZE))
+}
+
+/// Number of whole tiles in an image, checking its size fits the grid.
+fn tile_grid(image: &Image) -> Result<(u32, u32), String> {
+    let size = image.size();
+    if size.width % TILE_SIZE != 0 || size.height % TILE_SIZE != 0 {
+        return Err(format!("Image is {}x{}, not a multiple of {} pixels", size.width, size.height, TILE_SIZE));
+    }
+    Ok((size.width / TILE_SIZE, size.height / TILE_SIZE))
+}
+
+fn decode(data: &[u8], bytes_per_row: usize, tiles_per_row: u32) -> Result<Image, String> {
+    let tile_bytes = bytes_per_row * TILE_SIZE as usize;
+    if data.len() % tile_bytes != 0 {
+        return Err(format!("Tile data is {} bytes, not a multiple of {}", data.len(), tile_bytes));
+    }
+
+    let count = data.len() / tile_bytes;
+    let mut image = Image::new(try!(sheet_size(count, tiles_per_row)), to_shade(0));
+    for (t, tile) in data.chunks(tile_bytes).enumerate() {
+        let tx = (t as u32 % tiles_per_row) * TILE_SIZE;
+        let ty = (t as u32 / tiles_per_row) * TILE_SIZE;
+        for (y, row) in tile.chunks(bytes_per_row).enumerate() {
+            for x in 0..TILE_SIZE {
+                let bit = 7 - x;
+                let mut color = (row[0] >> bit) & 1;
+                if bytes_per_row == 2 {
+                    color |= ((row[1] >> bit) & 1) << 1;
+                } else {
+                    color *= 3;
+                }
+                try!(image.set_pixel((tx + x, ty + y as u32), to_shade(color)));
+            }
+        }
+    }
+    Ok(image)
+}
+
+fn encode(image: &Image, bytes_per_row: usize) -> Result<Vec<u8>, String> {
+    let (columns, rows) = try!(tile_grid(image));
+    let mut data = Vec::with_capacity((columns * rows) as usize * bytes_per_row * TILE_SIZE as usize);
+    for t in 0..columns * rows {
+        let tx = (t % columns) * TILE_SIZE;
+        let ty = (t / columns) * TILE_SIZE;
+        for y in 0..TILE_SIZE {
+            let (mut low, mut high) = (0u8, 0u8);
+            for x in 0..TILE_SIZE {
+                let color = to_color(try!(image.get_pixel((tx + x, ty + y))));
+                let bit = 7 - x;
+                if bytes_per_row == 2 {
+                    low |= (color & 1) << bit;
+                    high |= (color >> 1) << bit;
+                } else if color >= 2 {
+                    low |= 1 << bit;
+                }
+            }
+            data.push(low);
+            if bytes_per_row == 2 {
+                data.push(high);
+            }
+        }
+    }
+    Ok(data)
+}
+
+/// Decode 2bpp tiles into a sheet `tiles_per_row` tiles wide. Unused cells
+/// in the last row are left at shade 3.
+pub fn decode_2bpp(data: &[u8], tiles_per_row: u32) -> Result<Image, String> {
+    decode(data, 2, tiles_per_row)
+}
+
+/// Decode 1bpp tiles, set bits becoming shade 0 and clear ones shade 3.
+pub fn decode_1bpp(data: &[u8], tiles_per_row: u32) -> Result<Image, String> {
+    decode(data, 1, tiles_per_row)
+}
+
+/// Encode an image as 2bpp tiles, left to right then top to bottom.
+/// Transparent pixels become color 0, which sprites treat as transparent.
+pub fn encode_2bpp(image: &Image) -> Result<Vec<u8>, String> {
+    encode(image, 2)
+}
+
+/// Encode an image as 1bpp tiles. Shades 0 and 1 set the bit.
+pub fn encode_1bpp(image: &Image) -> Result<Vec<u8>, String> {
+    encode(image, 1)
+}
+
+fn read_all(path: &PathBuf) -> Result<Vec<u8>, String> {
+    let mut data = Vec::new();
+    let mut file = try!(File::open(path).map_err(|e| format!("{}: {}", path.display(), e)));
+    try!(file.read_to_end(&mut data).map_err(|e| format!("{}: {}", path.display(), e)));
+    Ok(data)
+}
+
+fn write_all(path: &PathBuf, data: &[u8]) -> Result<(), String> {
+    let mut file = try!(File::create(path).map_err(|e| format!("{}: {}", path.display(), e)));
+    file.write_all(data).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+pub fn load_2bpp(path: PathBuf, tiles_per_row: u32) -> Result<Image, String> {
+    decode_2bpp(&try!(read_all(&path)), tiles_per_row)
+}
+
+pub fn load_1bpp(path: PathBuf, tiles_per_row: u32) -> Result<Image, String> {
+    decode_1bpp(&try!(read_all(&path)), tiles_per_row)
+}
+
+pub fn save_2bpp(path: PathBuf, image: &Image) -> Result<(), String> {
+    write_all(&path, &try!(encode_2bpp(image)))
+}
+
+pub fn save_1bpp(path: PathBuf, image: &Image) -> Result<(), String> {
+    write_all(&path, &try!(encode_1bpp(image)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_2bpp, encode_2bpp, decode_1bpp, encode_1bpp};
+    use ::gfx::image::{Image, TRANSPARENT};
+
+    #[test]
+    fn decode_2bpp_row() {
+        // Low plane 0b0101..., high plane 0b0011... gives colors 0 1 2 3.
+        let mut data = vec![0u8; 16];
+        data[0] = 0b0101_0101;
+        data[1] = 0b0011_0011;
+        let image = decode_2bpp(&data, 1).unwrap();
+        assert_eq!(image.get_pixel((0, 0)), Ok(3));
+        assert_eq!(image.get_pixel((1, 0)), Ok(2));
+        assert_eq!(image.get_pixel((2, 0)), Ok(1));
+        assert_eq!(image.get_pixel((3, 0)), Ok(0));
+        assert_eq!(image.get_pixel((0, 1)), Ok(3));
+    }
+
+    #[test]
+    fn round_trip_2bpp() {
+        let mut image = Image::new((16, 8), 0u8);
+        for x in 0..16 {
+            for y in 0..8 {
+                image.set_pixel((x, y), ((x * 3 + y) % 4) as u8).unwrap();
+            }
+        }
+        let data = encode_2bpp(&image).unwrap();
+        assert_eq!(data.len(), 32);
+        let decoded = decode_2bpp(&data, 2).unwrap();
+        for x in 0..16 {
+            for y in 0..8 {
+                assert_eq!(decoded.get_pixel((x, y)), image.get_pixel((x, y)));
+            }
+        }
+    }
+
+    #[test]
+    fn transparent_encodes_as_color_zero() {
+        let image = Image::new((8, 8), TRANSPARENT);
+        assert!(encode_2bpp(&image).unwrap().iter().all(|b| *b == 0));
+        // Nor should any other out of range shade underflow.
+        let image = Image::new((8, 8), 7u8);
+        assert!(encode_2bpp(&image).unwrap().iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn round_trip_1bpp() {
+        let mut data = vec![0u8; 8];
+        data[3] = 0b1000_0001;
+        let image = decode_1bpp(&data, 1).unwrap();
+        assert_eq!(image.get_pixel((0, 3)), Ok(0));
+        assert_eq!(image.get_pixel((1, 3)), Ok(3));
+        assert_eq!(encode_1bpp(&image).unwrap(), data);
+    }
+}
@@ -1,5 +1,14 @@
+pub mod tiled;
+pub mod aseprite;
+pub mod quantize;
+pub mod gbtiles;
+pub mod palette;
+
 use std::path::PathBuf;
 use std::error::Error;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::rc::Rc;
 
 use find_folder::Search;
 
@@ -7,22 +16,80 @@ use image;
 use image::{DynamicImage, GenericImage, Rgba, Pixels, Pixel, GrayAlphaImage};
 
 use ::gfx::image::Image;
+use ::gfx::font::Font;
+use ::input::KeyBindings;
+use ::math::size::Size;
+
+pub use self::quantize::{ImportOptions, ShadeMapping};
+use self::quantize::quantize;
+
+/// A file in the `assets` folder, found near the working directory so the
+/// game runs from anywhere in the project. Falls back to `./assets`.
+pub fn asset_path(name: &str) -> PathBuf {
+    let folder = Search::ParentsThenKids(3, 3).for_folder("assets").unwrap_or_else(|_| PathBuf::from("assets"));
+    folder.join(name)
+}
 
 pub fn load_image(path: PathBuf) -> Result<Image, String> {
-    let (dims, buffer) = match image::open(path) {
-        Ok(i) => (i.dimensions(), i.to_luma_alpha()),
+    load_image_with(path, &ImportOptions::new())
+}
+
+/// Load an image, choosing how its colors become shades.
+pub fn load_image_with(path: PathBuf, options: &ImportOptions) -> Result<Image, String> {
+    let (dims, buffer) = match image::open(&path) {
+        Ok(i) => (i.dimensions(), i.to_rgba()),
         Err(e) => return Err(e.description().to_string())
     };
 
-    // convert DynamicImage to Image
-    let mut image = Image::new(dims, 0u8);
-    for (i, ps) in buffer.chunks(2).enumerate() {
-        if ps[1] > 240 {
-            try!(image.set_index(i, ps[0] / 64));
-        } else {
-            try!(image.set_index(i, 4));
+    quantize(&buffer, dims.0, dims.1, options).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Load a fixed-width font from a sheet of `glyph` sized cells starting at
+/// character `first`.
+pub fn load_font(path: PathBuf, glyph: Size, first: char) -> Result<Font, String> {
+    let sheet = try!(load_image(path));
+    Ok(Font::fixed(Rc::new(sheet), glyph, first))
+}
+
+/// Load a variable-width font from a sheet and its metrics file.
+pub fn load_font_with_metrics(image_path: PathBuf, metrics_path: PathBuf) -> Result<Font, String> {
+    let sheet = try!(load_image(image_path));
+
+    let mut metrics = String::new();
+    let mut file = try!(File::open(&metrics_path).map_err(|e| format!("{}: {}", metrics_path.display(), e)));
+    try!(file.read_to_string(&mut metrics).map_err(|e| format!("{}: {}", metrics_path.display(), e)));
+
+    Font::from_metrics(Rc::new(sheet), &metrics).map_err(|e| format!("{}: {}", metrics_path.display(), e))
+}
+
+/// Load key bindings from a config file written by `save_bindings`.
+pub fn load_bindings(path: PathBuf) -> Result<KeyBindings, String> {
+    let mut text = String::new();
+    let mut file = try!(File::open(&path).map_err(|e| format!("{}: {}", path.display(), e)));
+    try!(file.read_to_string(&mut text).map_err(|e| format!("{}: {}", path.display(), e)));
+    KeyBindings::from_config(&text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+pub fn save_bindings(path: PathBuf, bindings: &KeyBindings) -> Result<(), String> {
+    let mut file = try!(File::create(&path).map_err(|e| format!("{}: {}", path.display(), e)));
+    file.write_all(bindings.to_config().as_bytes()).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::gfx::palettes::default_colors;
+
+    #[test]
+    fn default_import_matches_palette_order() {
+        // the test image is drawn with the four default colors, darkest as
+        // shade 0 like the palette
+        let path = PathBuf::from("assets/test-img.png");
+        let default = load_image(path.clone()).unwrap();
+        let nearest = load_image_with(path, &ImportOptions::palette(default_colors())).unwrap();
+        assert_eq!(default.buffer, nearest.buffer);
+        for shade in 0..4 {
+            assert!(default.buffer.contains(&shade), "no pixels of shade {}", shade);
         }
     }
-
-    Ok(image)
 }
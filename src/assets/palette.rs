@@ -0,0 +1,140 @@
+//! Palette files: GIMP `.gpl`, JASC `.pal` and `.hex` (one `RRGGBB` per
+//! line, as exported by Lospec).
+//!
+//! A file must hold exactly four colors. They're sorted by luminance so the
+//! file may list them in either order.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+use ::gfx::Color;
+use ::gfx::palettes::Palette;
+
+fn parse_rgb(fields: &[&str]) -> Option<Color> {
+    if fields.len() < 3 {
+        return None;
+    }
+    match (fields[0].parse(), fields[1].parse(), fields[2].parse()) {
+        (Ok(r), Ok(g), Ok(b)) => Some([r, g, b, 255]),
+        _ => None
+    }
+}
+
+/// Colors from a GIMP palette. The name comes from its `Name:` line.
+pub fn parse_gpl(text: &str) -> Result<(Option<String>, Vec<Color>), String> {
+    let mut lines = text.lines();
+    if lines.next().map(|l| l.trim()) != Some("GIMP Palette") {
+        return Err("Missing \"GIMP Palette\" header".to_string());
+    }
+
+    let mut name = None;
+    let mut colors = Vec::new();
+    for (n, line) in lines.enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("Columns:") {
+            continue;
+        }
+        if line.starts_with("Name:") {
+            name = Some(line[5..].trim().to_string());
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        colors.push(try!(parse_rgb(&fields).ok_or(format!("Line {}: expected <r> <g> <b> [name]", n + 2))));
+    }
+    Ok((name, colors))
+}
+
+/// Colors from a JASC-PAL palette.
+pub fn parse_jasc(text: &str) -> Result<Vec<Color>, String> {
+    let lines: Vec<&str> = text.lines().map(|l| l.trim()).collect();
+    if lines.len() < 3 || lines[0] != "JASC-PAL" {
+        return Err("Missing \"JASC-PAL\" header".to_string());
+    }
+    let count: usize = try!(lines[2].parse().map_err(|_| format!("Invalid color count \"{}\"", lines[2])));
+
+    let mut colors = Vec::new();
+    for (n, line) in lines[3..].iter().enumerate().filter(|&(_, l)| !l.is_empty()) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        colors.push(try!(parse_rgb(&fields).ok_or(format!("Line {}: expected <r> <g> <b>", n + 4))));
+    }
+    if colors.len() != count {
+        return Err(format!("Header says {} colors, found {}", count, colors.len()));
+    }
+    Ok(colors)
+}
+
+/// Colors from hex codes, one per line, with or without a leading `#`.
+pub fn parse_hex(text: &str) -> Result<Vec<Color>, String> {
+    let mut colors = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim().trim_left_matches('#');
+        if line.is_empty() {
+            continue;
+        }
+        let v = try!(u32::from_str_radix(line, 16).ok()
+                     .and_then(|v| if line.len() == 6 { Some(v) } else { None })
+                     .ok_or(format!("Line {}: expected RRGGBB", n + 1)));
+        colors.push([(v >> 16) as u8, (v >> 8) as u8, v as u8, 255]);
+    }
+    Ok(colors)
+}
+
+/// Turn four colors in any order into a palette, darkest first.
+pub fn to_palette(name: &str, colors: &[Color]) -> Result<Palette, String> {
+    if colors.len() != 4 {
+        return Err(format!("A palette needs 4 colors, found {}", colors.len()));
+    }
+    let luma = |c: &Color| c[0] as u32 * 2126 + c[1] as u32 * 7152 + c[2] as u32 * 722;
+    let mut sorted = colors.to_vec();
+    sorted.sort_by_key(|c| luma(c));
+    Ok(Palette::new(name, [sorted[0], sorted[1], sorted[2], sorted[3]]))
+}
+
+/// Load a palette file, picking the format from its extension. Palettes are
+/// named after the file unless a `.gpl` names itself.
+pub fn load_palette(path: PathBuf) -> Result<Palette, String> {
+    let mut text = String::new();
+    let mut file = try!(File::open(&path).map_err(|e| format!("{}: {}", path.display(), e)));
+    try!(file.read_to_string(&mut text).map_err(|e| format!("{}: {}", path.display(), e)));
+
+    let stem = path.file_stem().map_or(String::new(), |s| s.to_string_lossy().into_owned());
+    let extension = path.extension().map_or(String::new(), |e| e.to_string_lossy().to_lowercase());
+    let result = match &extension[..] {
+        "gpl" => parse_gpl(&text).and_then(|(name, colors)| to_palette(&name.unwrap_or(stem), &colors)),
+        "pal" => parse_jasc(&text).and_then(|colors| to_palette(&stem, &colors)),
+        "hex" => parse_hex(&text).and_then(|colors| to_palette(&stem, &colors)),
+        _ => Err(format!("Unknown palette format \"{}\"", extension))
+    };
+    result.map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_gpl, parse_jasc, parse_hex, to_palette};
+
+    #[test]
+    fn gpl() {
+        let text = "GIMP Palette\nName: Test\nColumns: 4\n# comment\n255 255 255 White\n  0   0   0\t Black\n";
+        let (name, colors) = parse_gpl(text).unwrap();
+        assert_eq!(name, Some("Test".to_string()));
+        assert_eq!(colors, vec![[255, 255, 255, 255], [0, 0, 0, 255]]);
+    }
+
+    #[test]
+    fn jasc() {
+        let text = "JASC-PAL\r\n0100\r\n2\r\n1 2 3\r\n4 5 6\r\n";
+        assert_eq!(parse_jasc(text).unwrap(), vec![[1, 2, 3, 255], [4, 5, 6, 255]]);
+        assert!(parse_jasc("JASC-PAL\n0100\n3\n1 2 3\n").is_err());
+    }
+
+    #[test]
+    fn hex_sorted_darkest_first() {
+        let colors = parse_hex("e0f8d0\n#88c070\n346856\n081820\n").unwrap();
+        let palette = to_palette("bgb", &colors).unwrap();
+        assert_eq!(palette.colors[0], [0x08, 0x18, 0x20, 255]);
+        assert_eq!(palette.colors[3], [0xe0, 0xf8, 0xd0, 255]);
+        assert!(parse_hex("12345\n").is_err());
+        assert!(to_palette("short", &colors[..3]).is_err());
+    }
+}
@@ -0,0 +1,208 @@
+//! Conversion of true color images to the four shades used by `Image`.
+
+use std::collections::HashSet;
+
+use ::gfx::Color;
+use ::gfx::image::{Image, TRANSPARENT};
+
+/// How opaque colors become shades. Shade 0 is the darkest.
+#[derive(Clone, Debug)]
+pub enum ShadeMapping {
+    /// Compare luminance against three ascending thresholds: below the first
+    /// is shade 0, at or above the last is shade 3.
+    Luma([u8; 3]),
+    /// Pick the closest color of a darkest first palette.
+    Nearest([Color; 4]),
+    /// Map exact RGB values to shades. Any other color is an error.
+    Exact(Vec<([u8; 3], u8)>)
+}
+
+#[derive(Clone, Debug)]
+pub struct ImportOptions {
+    pub mapping: ShadeMapping,
+    /// Apply a 4x4 ordered dither before `Luma` or `Nearest` mapping.
+    pub dither: bool,
+    /// Pixels with alpha at or below this are transparent.
+    pub alpha_threshold: u8,
+    /// An RGB color treated as transparent.
+    pub color_key: Option<[u8; 3]>,
+    /// Fail on images with more than four distinct opaque colors.
+    pub strict: bool
+}
+
+impl ImportOptions {
+    /// Matches what `load_image` has always done: even luminance bands and
+    /// alpha above 240 is opaque.
+    pub fn new() -> ImportOptions {
+        ImportOptions {
+            mapping: ShadeMapping::Luma([64, 128, 192]),
+            dither: false,
+            alpha_threshold: 240,
+            color_key: None,
+            strict: false
+        }
+    }
+
+    /// Nearest match against a palette, for art drawn with its colors.
+    pub fn palette(colors: [Color; 4]) -> ImportOptions {
+        let mut options = ImportOptions::new();
+        options.mapping = ShadeMapping::Nearest(colors);
+        options
+    }
+}
+
+const BAYER_4X4: [[u8; 4]; 4] = [
+    [0, 8, 2, 10],
+    [12, 4, 14, 6],
+    [3, 11, 1, 9],
+    [15, 7, 13, 5]
+];
+
+/// Spread of the dither, about one shade step.
+const DITHER_SPREAD: f32 = 64.0;
+
+#[inline]
+fn luma(rgb: [f32; 3]) -> f32 {
+    rgb[0] * 0.2126 + rgb[1] * 0.7152 + rgb[2] * 0.0722
+}
+
+fn nearest(rgb: [f32; 3], colors: &[Color; 4]) -> u8 {
+    let mut best = 0;
+    let mut best_distance = ::std::f32::MAX;
+    for (i, c) in colors.iter().enumerate() {
+        let (dr, dg, db) = (rgb[0] - c[0] as f32, rgb[1] - c[1] as f32, rgb[2] - c[2] as f32);
+        let distance = dr * dr + dg * dg + db * db;
+        if distance < best_distance {
+            best = i as u8;
+            best_distance = distance;
+        }
+    }
+    best
+}
+
+/// Convert packed RGBA pixels, row first, into an `Image`.
+pub fn quantize(rgba: &[u8], width: u32, height: u32, options: &ImportOptions) -> Result<Image, String> {
+    if rgba.len() != (width * height * 4) as usize {
+        return Err("Pixel data doesn't match the image size".to_string());
+    }
+
+    let mut image = Image::new((width, height), TRANSPARENT);
+    let mut distinct = HashSet::new();
+
+    for (i, px) in rgba.chunks(4).enumerate() {
+        let key = [px[0], px[1], px[2]];
+        if px[3] <= options.alpha_threshold || options.color_key == Some(key) {
+            continue;
+        }
+
+        if options.strict {
+            distinct.insert(key);
+            if distinct.len() > 4 {
+                return Err(format!("More than 4 opaque colors, found #{:02x}{:02x}{:02x} as the fifth",
+                                   key[0], key[1], key[2]));
+            }
+        }
+
+        let (x, y) = (i as u32 % width, i as u32 / width);
+        let offset = if options.dither {
+            (BAYER_4X4[(y % 4) as usize][(x % 4) as usize] as f32 + 0.5) / 16.0 - 0.5
+        } else {
+            0.0
+        };
+        let rgb = [
+            px[0] as f32 + offset * DITHER_SPREAD,
+            px[1] as f32 + offset * DITHER_SPREAD,
+            px[2] as f32 + offset * DITHER_SPREAD
+        ];
+
+        let shade = match options.mapping {
+            ShadeMapping::Luma(thresholds) => {
+                let l = luma(rgb);
+                thresholds.iter().take_while(|t| l >= **t as f32).count() as u8
+            },
+            ShadeMapping::Nearest(ref colors) => nearest(rgb, colors),
+            ShadeMapping::Exact(ref table) => {
+                match table.iter().find(|&&(c, _)| c == key) {
+                    Some(&(_, shade)) => shade,
+                    None => return Err(format!("Color #{:02x}{:02x}{:02x} has no shade", key[0], key[1], key[2]))
+                }
+            }
+        };
+
+        try!(image.set_index(i, shade));
+    }
+
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{quantize, ImportOptions, ShadeMapping};
+
+    fn rgba(colors: &[[u8; 4]]) -> Vec<u8> {
+        colors.iter().flat_map(|c| c.iter().cloned()).collect()
+    }
+
+    #[test]
+    fn default_matches_old_red_bands() {
+        // `load_image` used to take red / 64 of grey sprites as the shade.
+        let greys: Vec<[u8; 4]> = (0..256).map(|v| [v as u8, v as u8, v as u8, 255]).collect();
+        let image = quantize(&rgba(&greys), 256, 1, &ImportOptions::new()).unwrap();
+        let old: Vec<u8> = (0..256).map(|v| (v / 64) as u8).collect();
+        assert_eq!(image.buffer, old);
+    }
+
+    #[test]
+    fn alpha_threshold_and_color_key() {
+        let mut options = ImportOptions::new();
+        options.color_key = Some([255, 0, 255]);
+        let pixels = rgba(&[[0, 0, 0, 240], [0, 0, 0, 241], [255, 0, 255, 255], [255, 255, 255, 255]]);
+        assert_eq!(quantize(&pixels, 4, 1, &options).unwrap().buffer, vec![4, 0, 4, 3]);
+    }
+
+    #[test]
+    fn strict_rejects_a_fifth_color() {
+        let mut options = ImportOptions::new();
+        options.strict = true;
+        let four = [[0, 0, 0, 255], [80, 80, 80, 255], [0, 0, 0, 255], [160, 160, 160, 255],
+                    [1, 2, 3, 0], [250, 250, 250, 255]];
+        assert!(quantize(&rgba(&four), 6, 1, &options).is_ok());
+
+        let mut five = four.to_vec();
+        five.push([0x12, 0x34, 0x56, 255]);
+        let err = quantize(&rgba(&five), 7, 1, &options).err().unwrap();
+        assert!(err.contains("#123456"), "{}", err);
+
+        options.strict = false;
+        assert!(quantize(&rgba(&five), 7, 1, &options).is_ok());
+    }
+
+    #[test]
+    fn exact_table() {
+        let mut options = ImportOptions::new();
+        options.mapping = ShadeMapping::Exact(vec![([10, 20, 30], 2), ([0, 0, 0], 1)]);
+        let pixels = rgba(&[[10, 20, 30, 255], [0, 0, 0, 255], [9, 9, 9, 0]]);
+        assert_eq!(quantize(&pixels, 3, 1, &options).unwrap().buffer, vec![2, 1, 4]);
+
+        let err = quantize(&rgba(&[[10, 20, 31, 255]]), 1, 1, &options).err().unwrap();
+        assert!(err.contains("#0a141f"), "{}", err);
+    }
+
+    #[test]
+    fn nearest_palette_color() {
+        let options = ImportOptions::palette([[15, 56, 15, 255], [48, 98, 48, 255],
+                                              [139, 172, 15, 255], [155, 188, 15, 255]]);
+        let pixels = rgba(&[[0, 0, 0, 255], [50, 100, 50, 255], [140, 170, 20, 255], [255, 255, 255, 255]]);
+        assert_eq!(quantize(&pixels, 4, 1, &options).unwrap().buffer, vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn dither_mixes_neighbouring_shades() {
+        let mut options = ImportOptions::new();
+        options.dither = true;
+        let grey = vec![[128, 128, 128, 255]; 16];
+        let image = quantize(&rgba(&grey), 4, 4, &options).unwrap();
+        assert!(image.buffer.contains(&1) && image.buffer.contains(&2));
+        assert!(image.buffer.iter().all(|&s| s == 1 || s == 2));
+    }
+}
@@ -0,0 +1,892 @@
+//! Importer for maps made with the Tiled editor, in either the `.tmx` (XML)
+//! or `.json` format. Tile layers become `Tilemap`s over the map's tilesets
+//! and object layers can spawn entities.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use rustc_serialize::base64::FromBase64;
+use rustc_serialize::json::Json;
+
+use xml::attribute::OwnedAttribute;
+use xml::reader::{EventReader, XmlEvent};
+
+use ::assets::load_image;
+use ::game::entitybuilder::EntityBuilder;
+use ::game::world::{World, EntityID};
+use ::gfx::image::{Image, SubImage};
+use ::gfx::tilemap::{Tilemap, TileFlip, TILE_SIZE};
+use ::gfx::blit::Rotation;
+use ::math::Vector;
+use ::math::rect::Rect;
+
+/// Tiled stores flip flags in the top bits of a gid.
+const GID_FLAGS: u32 = 0xE0000000;
+const FLIPPED_HORIZONTALLY: u32 = 0x80000000;
+const FLIPPED_VERTICALLY: u32 = 0x40000000;
+const FLIPPED_DIAGONALLY: u32 = 0x20000000;
+
+pub struct TiledMap {
+    /// Map size in tiles.
+    pub width: u32,
+    pub height: u32,
+    pub layers: Vec<TileLayer>,
+    pub object_groups: Vec<ObjectGroup>,
+    pub properties: HashMap<String, String>
+}
+
+pub struct TileLayer {
+    pub name: String,
+    pub visible: bool,
+    pub tilemap: Tilemap
+}
+
+pub struct ObjectGroup {
+    pub name: String,
+    pub objects: Vec<TiledObject>
+}
+
+#[derive(Clone, Debug)]
+pub struct TiledObject {
+    pub id: u32,
+    pub name: String,
+    /// The object's type (called class in newer versions of Tiled).
+    pub kind: String,
+    /// Top left of the object in map pixels.
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    pub properties: HashMap<String, String>
+}
+
+/// Fills in an entity for a map object. The builder already has the object's
+/// position and the object itself attached as a component.
+pub type Spawner = Fn(&mut EntityBuilder, &TiledObject) -> ();
+
+/// A tileset before its image is sliced.
+struct TilesetDef {
+    first_gid: u32,
+    image: PathBuf,
+    tile_width: u32,
+    tile_height: u32,
+    margin: u32,
+    spacing: u32,
+    tile_count: Option<u32>
+}
+
+/// A tileset as a map refers to it: inline, or in another file.
+enum TilesetSource {
+    Inline(TilesetDef),
+    /// First gid and the path of a `.tsx` or `.json` tileset.
+    External(u32, PathBuf)
+}
+
+/// A map before its tilesets are loaded.
+struct MapDef {
+    width: u32,
+    height: u32,
+    tilesets: Vec<TilesetSource>,
+    layers: Vec<LayerDef>,
+    object_groups: Vec<ObjectGroup>,
+    properties: HashMap<String, String>
+}
+
+/// A tile layer before the tilesets are loaded.
+struct LayerDef {
+    name: String,
+    visible: bool,
+    width: u32,
+    height: u32,
+    gids: Vec<u32>
+}
+
+/// Load a `.tmx` or `.json` map, picking the format from the extension.
+/// External tilesets may be in either format, whatever the map uses.
+pub fn load_map(path: PathBuf) -> Result<TiledMap, String> {
+    let text = try!(read_file(&path));
+    let dir = base_dir(&path);
+    let is_json = path.extension().map_or(false, |e| e == "json");
+    let def = if is_json {
+        parse_json_map(&text, &dir)
+    } else {
+        parse_tmx_map(&text, &dir)
+    };
+    build_map(try!(def.map_err(|e| format!("{}: {}", path.display(), e))))
+}
+
+impl TiledMap {
+    /// Create an entity for every object whose type has a spawner. Objects
+    /// with other types are skipped.
+    pub fn spawn_objects(&self, world: &mut World, spawners: &HashMap<String, Box<Spawner>>) -> Vec<EntityID> {
+        let mut spawned = Vec::new();
+        for group in self.object_groups.iter() {
+            for object in group.objects.iter() {
+                let spawner = match spawners.get(&object.kind) {
+                    Some(s) => s,
+                    None => {
+                        debug!("No spawner for object {} of type \"{}\"", object.id, object.kind);
+                        continue;
+                    }
+                };
+
+                let mut builder = EntityBuilder::new(world);
+                builder.position(Vector::new(object.x, object.y))
+                    .with(object.clone());
+                spawner(&mut builder, object);
+                spawned.push(builder.finish());
+            }
+        }
+        spawned
+    }
+
+    pub fn layer(&self, name: &str) -> Option<&TileLayer> {
+        self.layers.iter().find(|l| l.name == name)
+    }
+}
+
+fn read_file(path: &Path) -> Result<String, String> {
+    let mut file = try!(File::open(path).map_err(|e| format!("{}: {}", path.display(), e)));
+    let mut s = String::new();
+    try!(file.read_to_string(&mut s).map_err(|e| format!("{}: {}", path.display(), e)));
+    Ok(s)
+}
+
+fn base_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(p) => p.to_path_buf(),
+        None => PathBuf::new()
+    }
+}
+
+/// Decode base64 layer data into little endian gids.
+fn decode_base64_gids(data: &str) -> Result<Vec<u32>, String> {
+    let bytes = try!(data.trim().from_base64().map_err(|e| format!("Invalid base64 layer data: {}", e)));
+    if bytes.len() % 4 != 0 {
+        return Err("Layer data is not a multiple of 4 bytes".to_string());
+    }
+    Ok(bytes.chunks(4).map(|b| {
+        (b[0] as u32) | ((b[1] as u32) << 8) | ((b[2] as u32) << 16) | ((b[3] as u32) << 24)
+    }).collect())
+}
+
+fn decode_csv_gids(data: &str) -> Result<Vec<u32>, String> {
+    data.split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse::<u32>().map_err(|e| format!("Invalid tile gid \"{}\": {}", s, e)))
+        .collect()
+}
+
+/// Parse an external tileset, picking the format from its extension.
+fn parse_tileset(text: &str, source: &Path, first_gid: u32) -> Result<TilesetDef, String> {
+    let dir = base_dir(source);
+    if source.extension().map_or(false, |e| e == "json") {
+        let j = try!(Json::from_str(text).map_err(|e| e.to_string()));
+        json_tileset(&j, first_gid, &dir)
+    } else {
+        let events = try!(xml_events(text.as_bytes()));
+        tmx_tileset(&events, first_gid, &dir)
+    }
+}
+
+fn load_tileset(source: &Path, first_gid: u32) -> Result<TilesetDef, String> {
+    let text = try!(read_file(source));
+    parse_tileset(&text, source, first_gid).map_err(|e| format!("{}: {}", source.display(), e))
+}
+
+/// Slice tileset images, placing each tile at its gid - 1.
+fn slice_tilesets(mut sets: Vec<(TilesetDef, Rc<Image>)>) -> Result<Vec<SubImage>, String> {
+    sets.sort_by_key(|a| a.0.first_gid);
+
+    let mut tiles: Vec<SubImage> = Vec::new();
+    for &(ref def, ref image) in sets.iter() {
+        if def.tile_width != TILE_SIZE || def.tile_height != TILE_SIZE {
+            return Err(format!("Only {}x{} tiles are supported, {} has {}x{}",
+                               TILE_SIZE, TILE_SIZE, def.image.display(), def.tile_width, def.tile_height));
+        }
+
+        let size = image.size();
+        let step_x = def.tile_width + def.spacing;
+        let step_y = def.tile_height + def.spacing;
+        let columns = (size.width.saturating_sub(def.margin * 2) + def.spacing) / step_x;
+        let rows = (size.height.saturating_sub(def.margin * 2) + def.spacing) / step_y;
+        if columns == 0 {
+            return Err(format!("Tileset image {} is smaller than a tile", def.image.display()));
+        }
+        let count = def.tile_count.unwrap_or(columns * rows);
+
+        // Gids past the end of the previous tileset are never used, but keep
+        // indices lined up with them.
+        let start = (def.first_gid.saturating_sub(1)) as usize;
+        let filler = SubImage::new(image.clone(), Rect::zero());
+        while tiles.len() < start {
+            tiles.push(filler.clone());
+        }
+        tiles.truncate(start);
+
+        for i in 0..count {
+            let (column, row) = (i % columns, i / columns);
+            let rect = Rect::new((def.margin + column * step_x) as i32, (def.margin + row * step_y) as i32,
+                                 def.tile_width, def.tile_height);
+            tiles.push(SubImage::new(image.clone(), rect));
+        }
+    }
+    Ok(tiles)
+}
+
+/// The flips and rotation for a gid's flags. Tiled flips diagonally first,
+/// then horizontally and vertically; a diagonal flip is a flip and a quarter
+/// turn in `BlitOptions` terms.
+fn tile_flip(gid: u32) -> TileFlip {
+    let (h, v) = (gid & FLIPPED_HORIZONTALLY != 0, gid & FLIPPED_VERTICALLY != 0);
+    if gid & FLIPPED_DIAGONALLY == 0 {
+        return TileFlip { flip_x: h, flip_y: v, rotation: Rotation::None };
+    }
+    match (h, v) {
+        (false, false) => TileFlip { flip_x: false, flip_y: true, rotation: Rotation::Cw90 },
+        (true, false) => TileFlip { flip_x: false, flip_y: false, rotation: Rotation::Cw90 },
+        (false, true) => TileFlip { flip_x: false, flip_y: false, rotation: Rotation::Cw270 },
+        (true, true) => TileFlip { flip_x: true, flip_y: false, rotation: Rotation::Cw90 }
+    }
+}
+
+/// Tile indices and flips for layer gids.
+fn layer_tiles(gids: &[u32]) -> Result<(Vec<Option<u16>>, Vec<TileFlip>), String> {
+    let mut tiles = Vec::with_capacity(gids.len());
+    let mut flips = Vec::with_capacity(gids.len());
+    for &gid in gids.iter() {
+        let index = gid & !GID_FLAGS;
+        if index > 0x10000 {
+            return Err(format!("Tile gid {} is above the 65536 tiles a map can use", index));
+        }
+        tiles.push(if index == 0 { None } else { Some((index - 1) as u16) });
+        flips.push(tile_flip(gid));
+    }
+    Ok((tiles, flips))
+}
+
+/// Load external tilesets and every tileset image, then build the layers.
+fn build_map(def: MapDef) -> Result<TiledMap, String> {
+    let mut sets = Vec::new();
+    for source in def.tilesets.into_iter() {
+        let tileset = match source {
+            TilesetSource::Inline(t) => t,
+            TilesetSource::External(first_gid, path) => try!(load_tileset(&path, first_gid))
+        };
+        let image = Rc::new(try!(load_image(tileset.image.clone())));
+        sets.push((tileset, image));
+    }
+    let tileset = try!(slice_tilesets(sets));
+
+    let mut layers = Vec::new();
+    for layer in def.layers.into_iter() {
+        let (tiles, flips) = try!(layer_tiles(&layer.gids).map_err(|e| format!("Layer \"{}\": {}", layer.name, e)));
+        let mut tilemap = try!(Tilemap::from_tiles(tileset.clone(), layer.width, layer.height, tiles));
+        try!(tilemap.set_flips(flips));
+        layers.push(TileLayer {
+            name: layer.name,
+            visible: layer.visible,
+            tilemap: tilemap
+        });
+    }
+
+    Ok(TiledMap {
+        width: def.width,
+        height: def.height,
+        layers: layers,
+        object_groups: def.object_groups,
+        properties: def.properties
+    })
+}
+
+// JSON
+
+fn json_u32(j: &Json, key: &str) -> Option<u32> {
+    j.find(key).and_then(|v| v.as_u64()).map(|v| v as u32)
+}
+
+fn json_f32(j: &Json, key: &str) -> f32 {
+    j.find(key).and_then(|v| v.as_f64()).unwrap_or(0.0) as f32
+}
+
+fn json_string(j: &Json, key: &str) -> String {
+    j.find(key).and_then(|v| v.as_string()).unwrap_or("").to_string()
+}
+
+fn json_value_string(v: &Json) -> String {
+    match *v {
+        Json::String(ref s) => s.clone(),
+        ref other => other.to_string()
+    }
+}
+
+/// Properties are an object in old versions of Tiled and a list of
+/// name/value pairs in newer ones.
+fn json_properties(j: &Json) -> HashMap<String, String> {
+    let mut properties = HashMap::new();
+    match j.find("properties") {
+        Some(&Json::Object(ref o)) => {
+            for (k, v) in o.iter() {
+                properties.insert(k.clone(), json_value_string(v));
+            }
+        },
+        Some(&Json::Array(ref a)) => {
+            for p in a.iter() {
+                if let (Some(name), Some(value)) = (p.find("name").and_then(|n| n.as_string()), p.find("value")) {
+                    properties.insert(name.to_string(), json_value_string(value));
+                }
+            }
+        },
+        _ => ()
+    }
+    properties
+}
+
+fn json_tileset(j: &Json, first_gid: u32, dir: &Path) -> Result<TilesetDef, String> {
+    let image = match j.find("image").and_then(|v| v.as_string()) {
+        Some(i) => i,
+        None => return Err("Tilesets made of separate images are not supported".to_string())
+    };
+    Ok(TilesetDef {
+        first_gid: first_gid,
+        image: dir.join(image),
+        tile_width: json_u32(j, "tilewidth").unwrap_or(0),
+        tile_height: json_u32(j, "tileheight").unwrap_or(0),
+        margin: json_u32(j, "margin").unwrap_or(0),
+        spacing: json_u32(j, "spacing").unwrap_or(0),
+        tile_count: json_u32(j, "tilecount")
+    })
+}
+
+fn json_object(j: &Json) -> TiledObject {
+    let mut kind = json_string(j, "type");
+    if kind.is_empty() {
+        kind = json_string(j, "class");
+    }
+
+    let height = json_f32(j, "height");
+    let mut y = json_f32(j, "y");
+    // Tile objects are positioned by their bottom left.
+    if j.find("gid").is_some() {
+        y -= height;
+    }
+
+    TiledObject {
+        id: json_u32(j, "id").unwrap_or(0),
+        name: json_string(j, "name"),
+        kind: kind,
+        x: json_f32(j, "x"),
+        y: y,
+        width: json_f32(j, "width"),
+        height: height,
+        properties: json_properties(j)
+    }
+}
+
+fn json_layers(j: &Json, layers: &mut Vec<LayerDef>, groups: &mut Vec<ObjectGroup>) -> Result<(), String> {
+    let list = match j.find("layers").and_then(|l| l.as_array()) {
+        Some(l) => l,
+        None => return Ok(())
+    };
+
+    for layer in list.iter() {
+        let name = json_string(layer, "name");
+        match layer.find("type").and_then(|t| t.as_string()).unwrap_or("") {
+            "tilelayer" => {
+                if layer.find("compression").and_then(|c| c.as_string()).map_or(false, |c| !c.is_empty()) {
+                    return Err(format!("Layer \"{}\" is compressed, which is not supported", name));
+                }
+                let gids = match layer.find("data") {
+                    Some(&Json::Array(ref a)) => a.iter().map(|g| g.as_u64().unwrap_or(0) as u32).collect(),
+                    Some(&Json::String(ref s)) => try!(decode_base64_gids(s)),
+                    _ => return Err(format!("Layer \"{}\" has no tile data", name))
+                };
+                layers.push(LayerDef {
+                    name: name,
+                    visible: layer.find("visible").and_then(|v| v.as_boolean()).unwrap_or(true),
+                    width: json_u32(layer, "width").unwrap_or(0),
+                    height: json_u32(layer, "height").unwrap_or(0),
+                    gids: gids
+                });
+            },
+            "objectgroup" => {
+                let objects = match layer.find("objects").and_then(|o| o.as_array()) {
+                    Some(o) => o.iter().map(json_object).collect(),
+                    None => Vec::new()
+                };
+                groups.push(ObjectGroup {
+                    name: name,
+                    objects: objects
+                });
+            },
+            "group" => try!(json_layers(layer, layers, groups)),
+            other => debug!("Skipping layer \"{}\" of type {}", name, other)
+        }
+    }
+    Ok(())
+}
+
+/// Parse a JSON map. Relative tileset paths are resolved against `dir`.
+fn parse_json_map(text: &str, dir: &Path) -> Result<MapDef, String> {
+    let j = try!(Json::from_str(text).map_err(|e| e.to_string()));
+
+    let mut tilesets = Vec::new();
+    if let Some(list) = j.find("tilesets").and_then(|t| t.as_array()) {
+        for t in list.iter() {
+            let first_gid = json_u32(t, "firstgid").unwrap_or(1);
+            match t.find("source").and_then(|s| s.as_string()) {
+                Some(source) => tilesets.push(TilesetSource::External(first_gid, dir.join(source))),
+                None => tilesets.push(TilesetSource::Inline(try!(json_tileset(t, first_gid, dir))))
+            }
+        }
+    }
+
+    let mut layers = Vec::new();
+    let mut groups = Vec::new();
+    try!(json_layers(&j, &mut layers, &mut groups));
+
+    Ok(MapDef {
+        width: json_u32(&j, "width").unwrap_or(0),
+        height: json_u32(&j, "height").unwrap_or(0),
+        tilesets: tilesets,
+        layers: layers,
+        object_groups: groups,
+        properties: json_properties(&j)
+    })
+}
+
+// TMX
+
+fn attr<'a>(attributes: &'a [OwnedAttribute], name: &str) -> Option<&'a str> {
+    attributes.iter().find(|a| a.name.local_name == name).map(|a| &a.value[..])
+}
+
+fn attr_u32(attributes: &[OwnedAttribute], name: &str) -> Option<u32> {
+    attr(attributes, name).and_then(|v| v.parse().ok())
+}
+
+fn attr_f32(attributes: &[OwnedAttribute], name: &str) -> f32 {
+    attr(attributes, name).and_then(|v| v.parse().ok()).unwrap_or(0.0)
+}
+
+fn xml_events<R: Read>(source: R) -> Result<Vec<XmlEvent>, String> {
+    let mut events = Vec::new();
+    for e in EventReader::new(source) {
+        match e {
+            Ok(e) => events.push(e),
+            Err(e) => return Err(e.to_string())
+        }
+    }
+    Ok(events)
+}
+
+/// Parse a `<tileset>` element, either inline in a map or in a `.tsx` file.
+fn tmx_tileset(events: &[XmlEvent], first_gid: u32, dir: &Path) -> Result<TilesetDef, String> {
+    let mut def = TilesetDef {
+        first_gid: first_gid,
+        image: PathBuf::new(),
+        tile_width: 0,
+        tile_height: 0,
+        margin: 0,
+        spacing: 0,
+        tile_count: None
+    };
+    let mut has_image = false;
+
+    for e in events.iter() {
+        match *e {
+            XmlEvent::StartElement { ref name, ref attributes, .. } if name.local_name == "tileset" => {
+                def.tile_width = attr_u32(attributes, "tilewidth").unwrap_or(0);
+                def.tile_height = attr_u32(attributes, "tileheight").unwrap_or(0);
+                def.margin = attr_u32(attributes, "margin").unwrap_or(0);
+                def.spacing = attr_u32(attributes, "spacing").unwrap_or(0);
+                def.tile_count = attr_u32(attributes, "tilecount");
+            },
+            XmlEvent::StartElement { ref name, ref attributes, .. } if name.local_name == "image" && !has_image => {
+                if let Some(source) = attr(attributes, "source") {
+                    def.image = dir.join(source);
+                    has_image = true;
+                }
+            },
+            XmlEvent::EndElement { ref name } if name.local_name == "tileset" => break,
+            _ => ()
+        }
+    }
+
+    if !has_image {
+        return Err("Tilesets made of separate images are not supported".to_string());
+    }
+    Ok(def)
+}
+
+/// Parse a TMX map. Relative tileset paths are resolved against `dir`.
+fn parse_tmx_map(text: &str, dir: &Path) -> Result<MapDef, String> {
+    let events = try!(xml_events(text.as_bytes()));
+
+    let (mut width, mut height) = (0, 0);
+    let mut tilesets = Vec::new();
+    let mut layers: Vec<LayerDef> = Vec::new();
+    let mut groups: Vec<ObjectGroup> = Vec::new();
+    let mut map_properties = HashMap::new();
+
+    // Parser state. Properties belong to whichever of these is innermost.
+    let mut layer: Option<LayerDef> = None;
+    let mut encoding = String::new();
+    let mut in_data = false;
+    let mut group: Option<ObjectGroup> = None;
+    let mut object: Option<TiledObject> = None;
+    let mut property: Option<String> = None;
+
+    let mut i = 0;
+    while i < events.len() {
+        match events[i] {
+            XmlEvent::StartElement { ref name, ref attributes, .. } => match &name.local_name[..] {
+                "map" => {
+                    width = attr_u32(attributes, "width").unwrap_or(0);
+                    height = attr_u32(attributes, "height").unwrap_or(0);
+                },
+                "tileset" => {
+                    let first_gid = attr_u32(attributes, "firstgid").unwrap_or(1);
+                    match attr(attributes, "source") {
+                        Some(source) => tilesets.push(TilesetSource::External(first_gid, dir.join(source))),
+                        None => tilesets.push(TilesetSource::Inline(try!(tmx_tileset(&events[i..], first_gid, dir))))
+                    }
+                    // Skip to the end of the tileset.
+                    while i < events.len() {
+                        if let XmlEvent::EndElement { ref name } = events[i] {
+                            if name.local_name == "tileset" { break; }
+                        }
+                        i += 1;
+                    }
+                },
+                "layer" => {
+                    layer = Some(LayerDef {
+                        name: attr(attributes, "name").unwrap_or("").to_string(),
+                        visible: attr(attributes, "visible") != Some("0"),
+                        width: attr_u32(attributes, "width").unwrap_or(0),
+                        height: attr_u32(attributes, "height").unwrap_or(0),
+                        gids: Vec::new()
+                    });
+                },
+                "data" => {
+                    if attr(attributes, "compression").is_some() {
+                        return Err("Compressed layer data is not supported".to_string());
+                    }
+                    encoding = attr(attributes, "encoding").unwrap_or("").to_string();
+                    in_data = true;
+                },
+                "tile" if in_data => {
+                    if let Some(ref mut l) = layer {
+                        l.gids.push(attr_u32(attributes, "gid").unwrap_or(0));
+                    }
+                },
+                "objectgroup" => {
+                    group = Some(ObjectGroup {
+                        name: attr(attributes, "name").unwrap_or("").to_string(),
+                        objects: Vec::new()
+                    });
+                },
+                "object" => {
+                    let height = attr_f32(attributes, "height");
+                    let mut y = attr_f32(attributes, "y");
+                    // Tile objects are positioned by their bottom left.
+                    if attr(attributes, "gid").is_some() {
+                        y -= height;
+                    }
+                    object = Some(TiledObject {
+                        id: attr_u32(attributes, "id").unwrap_or(0),
+                        name: attr(attributes, "name").unwrap_or("").to_string(),
+                        kind: attr(attributes, "type").or(attr(attributes, "class")).unwrap_or("").to_string(),
+                        x: attr_f32(attributes, "x"),
+                        y: y,
+                        width: attr_f32(attributes, "width"),
+                        height: height,
+                        properties: HashMap::new()
+                    });
+                },
+                "property" => {
+                    let key = attr(attributes, "name").unwrap_or("").to_string();
+                    match attr(attributes, "value") {
+                        Some(value) => {
+                            let value = value.to_string();
+                            if let Some(ref mut o) = object {
+                                o.properties.insert(key, value);
+                            } else if group.is_none() && layer.is_none() {
+                                map_properties.insert(key, value);
+                            }
+                        },
+                        // Multi-line values are stored as the element's text.
+                        None => property = Some(key)
+                    }
+                },
+                _ => ()
+            },
+            XmlEvent::Characters(ref text) => {
+                if in_data {
+                    if let Some(ref mut l) = layer {
+                        let gids = match &encoding[..] {
+                            "csv" => try!(decode_csv_gids(text)),
+                            "base64" => try!(decode_base64_gids(text)),
+                            other => return Err(format!("Unknown layer encoding \"{}\"", other))
+                        };
+                        l.gids.extend(gids);
+                    }
+                } else if let Some(key) = property.take() {
+                    if let Some(ref mut o) = object {
+                        o.properties.insert(key, text.clone());
+                    } else if group.is_none() && layer.is_none() {
+                        map_properties.insert(key, text.clone());
+                    }
+                }
+            },
+            XmlEvent::EndElement { ref name } => match &name.local_name[..] {
+                "data" => in_data = false,
+                "layer" => {
+                    if let Some(l) = layer.take() {
+                        layers.push(l);
+                    }
+                },
+                "object" => {
+                    if let (Some(o), Some(g)) = (object.take(), group.as_mut()) {
+                        g.objects.push(o);
+                    }
+                },
+                "objectgroup" => {
+                    if let Some(g) = group.take() {
+                        groups.push(g);
+                    }
+                },
+                "property" => property = None,
+                _ => ()
+            },
+            _ => ()
+        }
+        i += 1;
+    }
+
+    Ok(MapDef {
+        width: width,
+        height: height,
+        tilesets: tilesets,
+        layers: layers,
+        object_groups: groups,
+        properties: map_properties
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{TilesetDef, TilesetSource, decode_csv_gids, decode_base64_gids, layer_tiles, tile_flip,
+                slice_tilesets, parse_json_map, parse_tmx_map, parse_tileset,
+                FLIPPED_HORIZONTALLY, FLIPPED_VERTICALLY};
+    use ::gfx::blit::{BlitOptions, Rotation};
+    use ::gfx::tilemap::TileFlip;
+    use ::math::rect::Rect;
+    use ::testing::solid;
+
+    fn def(first_gid: u32) -> TilesetDef {
+        TilesetDef {
+            first_gid: first_gid,
+            image: PathBuf::from("tiles.png"),
+            tile_width: 8,
+            tile_height: 8,
+            margin: 0,
+            spacing: 0,
+            tile_count: None
+        }
+    }
+
+    #[test]
+    fn csv_and_base64_gids() {
+        assert_eq!(decode_csv_gids("1,2,\n0, 3\n"), Ok(vec![1, 2, 0, 3]));
+        assert!(decode_csv_gids("1,x").is_err());
+        // 1, 2 and 0x80000003 as little endian u32s
+        assert_eq!(decode_base64_gids(" AQAAAAIAAAADAACA \n"), Ok(vec![1, 2, 0x80000003]));
+        assert!(decode_base64_gids("AQAA").is_err());
+    }
+
+    #[test]
+    fn flip_flags_become_tile_flips() {
+        let (tiles, flips) = layer_tiles(&[0, 1, 0x80000003, 0x40000002, 0x20000001]).unwrap();
+        assert_eq!(tiles, vec![None, Some(0), Some(2), Some(1), Some(0)]);
+        assert_eq!(flips[1], TileFlip::none());
+        assert_eq!(flips[2], TileFlip { flip_x: true, flip_y: false, rotation: Rotation::None });
+        assert_eq!(flips[3], TileFlip { flip_x: false, flip_y: true, rotation: Rotation::None });
+
+        // a diagonal flip swaps x and y, so check it against that directly
+        let size = 4;
+        for &flags in [0x20000000, 0xA0000000, 0x60000000, 0xE0000000].iter() {
+            let options = BlitOptions::new().flip_x(tile_flip(flags).flip_x)
+                .flip_y(tile_flip(flags).flip_y).rotation(tile_flip(flags).rotation);
+            for y in 0..size {
+                for x in 0..size {
+                    // Tiled: swap, then mirror x, then mirror y
+                    let (mut sx, mut sy) = (x, y);
+                    if flags & FLIPPED_HORIZONTALLY != 0 { sx = size - 1 - sx; }
+                    if flags & FLIPPED_VERTICALLY != 0 { sy = size - 1 - sy; }
+                    assert_eq!(options.source_offset(x, y, size, size), (sy, sx), "{:x}", flags);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn gids_past_u16_are_an_error() {
+        assert_eq!(layer_tiles(&[0x10000]).unwrap().0, vec![Some(0xFFFF)]);
+        assert!(layer_tiles(&[0x10001]).is_err());
+        assert!(layer_tiles(&[0x80010001]).is_err());
+    }
+
+    #[test]
+    fn first_gids_place_tiles() {
+        // Two tiles from gid 1 and one from gid 5, leaving gids 3 and 4 unused.
+        let sets = vec![
+            (def(5), solid(8, 8, 0)),
+            (def(1), solid(16, 8, 0))
+        ];
+        let tiles = slice_tilesets(sets).unwrap();
+        let rects: Vec<Rect> = tiles.iter().map(|t| t.rect()).collect();
+        assert_eq!(rects, vec![Rect::new(0, 0, 8, 8), Rect::new(8, 0, 8, 8), Rect::zero(), Rect::zero(),
+                               Rect::new(0, 0, 8, 8)]);
+        assert_eq!(tiles[4].size().width, 8);
+    }
+
+    const JSON_MAP: &'static str = r#"{
+        "width": 2, "height": 1,
+        "properties": [{"name": "music", "type": "string", "value": "town"},
+                       {"name": "dark", "type": "bool", "value": true}],
+        "tilesets": [{"firstgid": 1, "source": "tiles.tsx"},
+                     {"firstgid": 10, "image": "more.png", "tilewidth": 8, "tileheight": 8, "tilecount": 4}],
+        "layers": [
+            {"type": "tilelayer", "name": "ground", "width": 2, "height": 1, "data": [1, 2147483659]},
+            {"type": "tilelayer", "name": "top", "width": 2, "height": 1, "visible": false,
+             "encoding": "base64", "data": "AQAAAAIAAAA="},
+            {"type": "group", "name": "g", "layers": [
+                {"type": "objectgroup", "name": "things", "objects": [
+                    {"id": 3, "name": "door", "type": "warp", "x": 8, "y": 32, "width": 16, "height": 16,
+                     "gid": 5, "properties": {"to": "house", "id": 2}},
+                    {"id": 4, "name": "zone", "class": "trigger", "x": 0, "y": 32, "width": 16, "height": 16}
+                ]}
+            ]}
+        ]
+    }"#;
+
+    #[test]
+    fn json_map() {
+        let map = parse_json_map(JSON_MAP, Path::new("maps")).unwrap();
+        assert_eq!((map.width, map.height), (2, 1));
+        assert_eq!(map.properties.get("music").map(|s| &s[..]), Some("town"));
+        assert_eq!(map.properties.get("dark").map(|s| &s[..]), Some("true"));
+
+        match map.tilesets[0] {
+            TilesetSource::External(1, ref path) => assert_eq!(path, &Path::new("maps").join("tiles.tsx")),
+            _ => panic!("expected an external tileset")
+        }
+        match map.tilesets[1] {
+            TilesetSource::Inline(ref t) => {
+                assert_eq!(t.first_gid, 10);
+                assert_eq!(t.image, Path::new("maps").join("more.png"));
+                assert_eq!(t.tile_count, Some(4));
+            },
+            _ => panic!("expected an inline tileset")
+        }
+
+        assert_eq!(map.layers.len(), 2);
+        assert_eq!(map.layers[0].gids, vec![1, 0x8000000B]);
+        assert_eq!(map.layers[1].gids, vec![1, 2]);
+        assert!(!map.layers[1].visible);
+
+        let objects = &map.object_groups[0].objects;
+        assert_eq!(map.object_groups[0].name, "things");
+        // The tile object's y moves up by its height; the plain one doesn't.
+        assert_eq!((objects[0].x, objects[0].y), (8.0, 16.0));
+        assert_eq!(objects[0].kind, "warp");
+        assert_eq!(objects[0].properties.get("to").map(|s| &s[..]), Some("house"));
+        assert_eq!(objects[0].properties.get("id").map(|s| &s[..]), Some("2"));
+        assert_eq!((objects[1].y, &objects[1].kind[..]), (32.0, "trigger"));
+    }
+
+    const TMX_MAP: &'static str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.10" orientation="orthogonal" width="2" height="2" tilewidth="8" tileheight="8">
+ <properties>
+  <property name="music" value="cave"/>
+  <property name="intro">Line one
+Line two</property>
+ </properties>
+ <tileset firstgid="1" source="tiles.json"/>
+ <tileset firstgid="5" name="inline" tilewidth="8" tileheight="8" spacing="1" margin="2" tilecount="3">
+  <image source="img/inline.png" width="32" height="16"/>
+ </tileset>
+ <layer id="1" name="csv" width="2" height="2">
+  <properties><property name="ignored" value="1"/></properties>
+  <data encoding="csv">
+1,2,
+0,3221225478
+</data>
+ </layer>
+ <layer id="2" name="b64" width="2" height="1" visible="0">
+  <data encoding="base64">
+   AQAAAAIAAAA=
+  </data>
+ </layer>
+ <objectgroup id="3" name="things">
+  <object id="1" name="chest" type="loot" gid="6" x="16" y="24" width="8" height="8">
+   <properties><property name="item" value="key"/></properties>
+  </object>
+  <object id="2" class="spawn" x="4" y="4"/>
+ </objectgroup>
+</map>"#;
+
+    #[test]
+    fn tmx_map() {
+        let map = parse_tmx_map(TMX_MAP, Path::new("maps")).unwrap();
+        assert_eq!((map.width, map.height), (2, 2));
+        assert_eq!(map.properties.get("music").map(|s| &s[..]), Some("cave"));
+        assert_eq!(map.properties.get("intro").map(|s| &s[..]), Some("Line one\nLine two"));
+        assert!(!map.properties.contains_key("ignored"));
+
+        match map.tilesets[0] {
+            TilesetSource::External(1, ref path) => assert_eq!(path, &Path::new("maps").join("tiles.json")),
+            _ => panic!("expected an external tileset")
+        }
+        match map.tilesets[1] {
+            TilesetSource::Inline(ref t) => {
+                assert_eq!((t.first_gid, t.margin, t.spacing, t.tile_count), (5, 2, 1, Some(3)));
+                assert_eq!(t.image, Path::new("maps").join("img/inline.png"));
+            },
+            _ => panic!("expected an inline tileset")
+        }
+
+        assert_eq!(map.layers[0].gids, vec![1, 2, 0, 0xC0000006]);
+        assert_eq!(map.layers[1].gids, vec![1, 2]);
+        assert!(map.layers[0].visible);
+        assert!(!map.layers[1].visible);
+
+        let objects = &map.object_groups[0].objects;
+        assert_eq!((objects[0].x, objects[0].y), (16.0, 16.0));
+        assert_eq!(objects[0].properties.get("item").map(|s| &s[..]), Some("key"));
+        assert_eq!((objects[1].y, &objects[1].kind[..]), (4.0, "spawn"));
+    }
+
+    #[test]
+    fn external_tileset_format_follows_its_extension() {
+        let tsx = r#"<?xml version="1.0"?>
+<tileset name="t" tilewidth="8" tileheight="8" tilecount="2"><image source="t.png"/></tileset>"#;
+        let t = parse_tileset(tsx, Path::new("maps/sets/t.tsx"), 3).unwrap();
+        assert_eq!((t.first_gid, t.tile_count), (3, Some(2)));
+        assert_eq!(t.image, Path::new("maps/sets").join("t.png"));
+
+        let json = r#"{"image": "t.png", "tilewidth": 8, "tileheight": 8, "spacing": 1}"#;
+        let t = parse_tileset(json, Path::new("maps/t.json"), 1).unwrap();
+        assert_eq!((t.spacing, t.tile_width), (1, 8));
+        assert_eq!(t.image, Path::new("maps").join("t.png"));
+
+        assert!(parse_tileset(json, Path::new("maps/t.tsx"), 1).is_err());
+    }
+}
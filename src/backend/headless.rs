@@ -0,0 +1,146 @@
+use std::collections::VecDeque;
+
+use ::backend::{Backend, Event};
+use ::gfx::screen::Screen;
+
+/// Renders frames into memory instead of a window, for running the game
+/// without a display. Its clock only moves when the game sleeps, so every
+/// run steps the same way.
+pub struct HeadlessBackend {
+    /// The last presented frame as packed RGB, row first.
+    pub framebuffer: Vec<u8>,
+    pub frames_presented: u64,
+    /// Microseconds each present takes on the simulated clock, to stand in
+    /// for a slow display.
+    pub present_time: u64,
+    frame_limit: Option<u64>,
+    clock: u64,
+    events: VecDeque<(u64, Event)>
+}
+
+impl HeadlessBackend {
+    /// Create a headless backend. With a `frame_limit`, a `Quit` event is
+    /// sent once that many frames have been presented.
+    pub fn new(frame_limit: Option<u64>) -> HeadlessBackend {
+        HeadlessBackend {
+            framebuffer: vec![0; 160 * 144 * 3],
+            frames_presented: 0,
+            present_time: 0,
+            frame_limit: frame_limit,
+            clock: 0,
+            events: VecDeque::new()
+        }
+    }
+
+    /// Queue an event to be delivered at the start of the given frame.
+    pub fn push_event(&mut self, frame: u64, event: Event) -> () {
+        let at = self.events.iter().position(|&(f, _)| f > frame).unwrap_or(self.events.len());
+        self.events.insert(at, (frame, event));
+    }
+}
+
+impl Backend for HeadlessBackend {
+    fn poll_events(&mut self) -> Vec<Event> {
+        let mut events = Vec::new();
+        while self.events.front().map_or(false, |&(f, _)| f <= self.frames_presented) {
+            if let Some((_, e)) = self.events.pop_front() {
+                events.push(e);
+            }
+        }
+
+        if let Some(limit) = self.frame_limit {
+            if self.frames_presented >= limit {
+                events.push(Event::Quit);
+            }
+        }
+        events
+    }
+
+    fn present(&mut self, screen: &Screen) -> Result<(), String> {
+        let len = screen.image.buffer.len() * 3;
+        self.framebuffer.resize(len, 0);
+        for (i, x) in screen.image.buffer.iter().enumerate() {
+            let color = screen.color(*x);
+            self.framebuffer[(i * 3) + 0] = color[0];
+            self.framebuffer[(i * 3) + 1] = color[1];
+            self.framebuffer[(i * 3) + 2] = color[2];
+        }
+        self.frames_presented += 1;
+        self.clock += self.present_time;
+        Ok(())
+    }
+
+    fn time(&mut self) -> u64 {
+        self.clock
+    }
+
+    fn sleep(&mut self, micros: u64) -> () {
+        self.clock += micros;
+    }
+
+    fn vsync(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HeadlessBackend;
+    use ::backend::{Event, Scancode};
+    use ::game::Game;
+    use ::gfx::image::Image;
+    use ::input::{PressedState, Button};
+    use ::testing::{Recorder, log};
+
+    /// The A button on each update of a run of `game`.
+    fn run_a(mut game: Game<HeadlessBackend>) -> Vec<PressedState> {
+        let scene = Recorder::new("a", &log(), Vec::new());
+        let inputs = scene.inputs.clone();
+        game.push_scene(scene);
+        game.run().unwrap();
+        let a = inputs.borrow().iter().map(|i| i.a).collect();
+        a
+    }
+
+    #[test]
+    fn runs_until_frame_limit() {
+        let scene = Recorder::new("a", &log(), Vec::new());
+        let inputs = scene.inputs.clone();
+        let mut game = Game::new(HeadlessBackend::new(Some(5)));
+        game.screen.borrow_mut().background = Image::new((160, 144), 1u8);
+        game.push_scene(scene);
+        game.run().unwrap();
+
+        assert_eq!(game.backend.frames_presented, 5);
+        assert_eq!(inputs.borrow().len(), 5);
+        assert!(!game.running);
+
+        let color = game.screen.borrow().color(1);
+        assert_eq!(game.backend.framebuffer.len(), 160 * 144 * 3);
+        assert!(game.backend.framebuffer.chunks(3).all(|px| px == &color[..3]));
+    }
+
+    #[test]
+    fn events_arrive_on_their_frame() {
+        let mut backend = HeadlessBackend::new(Some(4));
+        backend.push_event(1, Event::KeyDown(Scancode::Z));
+        backend.push_event(3, Event::KeyUp(Scancode::Z));
+
+        use ::input::PressedState::*;
+        assert_eq!(run_a(Game::new(backend)), vec![Up, Pressed, Held, Up]);
+    }
+
+    #[test]
+    fn button_stays_down_while_any_key_is() {
+        let mut backend = HeadlessBackend::new(Some(5));
+        backend.push_event(1, Event::KeyDown(Scancode::Z));
+        backend.push_event(2, Event::KeyDown(Scancode::C));
+        backend.push_event(3, Event::KeyUp(Scancode::Z));
+        backend.push_event(4, Event::KeyUp(Scancode::C));
+        let game = Game::new(backend);
+        game.bindings.borrow_mut().bind(Button::A, Scancode::C);
+
+        use ::input::PressedState::*;
+        assert_eq!(run_a(game), vec![Up, Pressed, Held, Held, Up]);
+    }
+}
@@ -0,0 +1,34 @@
+//! Video output, event sources and frame pacing live behind `Backend` so the
+//! game loop never touches a window directly.
+
+pub mod sdl;
+pub mod headless;
+
+use ::gfx::screen::Screen;
+
+pub use sdl2::keyboard::Scancode;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Event {
+    Quit,
+    KeyDown(Scancode),
+    KeyUp(Scancode)
+}
+
+pub trait Backend {
+    /// Drain the events received since the last call.
+    fn poll_events(&mut self) -> Vec<Event>;
+
+    /// Show the contents of the screen, mapping shades through its colors.
+    fn present(&mut self, screen: &Screen) -> Result<(), String>;
+
+    /// Microseconds since some fixed point, for frame pacing.
+    fn time(&mut self) -> u64;
+
+    /// Block for about `micros` microseconds.
+    fn sleep(&mut self, micros: u64) -> ();
+
+    /// Whether `present` waits for the display's vertical sync, in which case
+    /// the game loop doesn't sleep between frames.
+    fn vsync(&self) -> bool;
+}
@@ -0,0 +1,111 @@
+use std::thread;
+use std::time::Duration;
+
+use sdl2;
+use sdl2::EventPump;
+use sdl2::Sdl;
+use sdl2::TimerSubsystem;
+use sdl2::VideoSubsystem;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::video::{Window, WindowContext};
+
+use ::backend::{Backend, Event};
+use ::gfx::screen::Screen;
+
+pub struct SdlBackend {
+    pub sdl: Sdl,
+    pub video_subsystem: VideoSubsystem,
+    pub canvas: Canvas<Window>,
+    event_pump: EventPump,
+    /// Owns `render_texture`, which is built with `unsafe_textures` so the
+    /// two can live in one struct.
+    texture_creator: TextureCreator<WindowContext>,
+    render_texture: Texture,
+    timer: TimerSubsystem,
+    vsync: bool
+}
+
+impl SdlBackend {
+    /// Open a window. With `vsync`, presenting waits for the display.
+    pub fn new(title: &str, vsync: bool) -> Result<SdlBackend, String> {
+        let sdl = try!(sdl2::init());
+        let video = try!(sdl.video());
+        let mut window_builder = video.window(title, 640, 576);
+        let window = try!(window_builder.position_centered().resizable().build().map_err(|e| e.to_string()));
+        let mut canvas = if vsync {
+            try!(window.into_canvas().present_vsync().build().map_err(|e| e.to_string()))
+        } else {
+            try!(window.into_canvas().build().map_err(|e| e.to_string()))
+        };
+
+        let texture_creator = canvas.texture_creator();
+        let render_texture = try!(texture_creator.create_texture_streaming(PixelFormatEnum::RGB888, 160, 144)
+                                  .map_err(|e| e.to_string()));
+        try!(canvas.set_logical_size(160, 144).map_err(|e| e.to_string()));
+
+        let event_pump = try!(sdl.event_pump());
+        let timer = try!(sdl.timer());
+
+        Ok(SdlBackend {
+            sdl: sdl,
+            video_subsystem: video,
+            canvas: canvas,
+            event_pump: event_pump,
+            texture_creator: texture_creator,
+            render_texture: render_texture,
+            timer: timer,
+            vsync: vsync
+        })
+    }
+}
+
+impl Backend for SdlBackend {
+    fn poll_events(&mut self) -> Vec<Event> {
+        use sdl2::event::Event::*;
+
+        let mut events = Vec::new();
+        for e in self.event_pump.poll_iter() {
+            match e {
+                Quit { .. } => events.push(Event::Quit),
+                KeyUp { scancode: Some(s), .. } => events.push(Event::KeyUp(s)),
+                KeyDown { scancode: Some(s), repeat: false, .. } => events.push(Event::KeyDown(s)),
+                _ => ()
+            };
+        }
+        events
+    }
+
+    fn present(&mut self, screen: &Screen) -> Result<(), String> {
+        // copy custom screen buffer to render texture, mapping colors
+        try!(self.render_texture.with_lock(None, |buf, _pitch| {
+            for (i, x) in screen.image.buffer.iter().enumerate() {
+                let color = screen.color(*x);
+                // It's BGR for some reason?
+                buf[(i * 4) + 0] = color[2];
+                buf[(i * 4) + 1] = color[1];
+                buf[(i * 4) + 2] = color[0];
+            }
+            ()
+        }));
+
+        self.canvas.clear();
+        try!(self.canvas.copy(&self.render_texture, None, None));
+        self.canvas.present();
+        Ok(())
+    }
+
+    fn time(&mut self) -> u64 {
+        let counter = self.timer.performance_counter();
+        let frequency = self.timer.performance_frequency();
+        (counter / frequency) * 1_000_000 + (counter % frequency) * 1_000_000 / frequency
+    }
+
+    fn sleep(&mut self, micros: u64) -> () {
+        thread::sleep(Duration::new(micros / 1_000_000, (micros % 1_000_000) as u32 * 1000));
+    }
+
+    fn vsync(&self) -> bool {
+        self.vsync
+    }
+}
@@ -0,0 +1,20 @@
+use ::math::{Position, Vector};
+
+/// Maps world coordinates to screen coordinates. The camera position is the
+/// world point drawn at the top left of the screen.
+#[derive(Copy, Clone)]
+pub struct Camera {
+    pub position: Vector
+}
+
+impl Camera {
+    pub fn new() -> Camera {
+        Camera { position: Vector::zero() }
+    }
+
+    /// Screen pixel for a world position, rounded to the nearest pixel.
+    #[inline]
+    pub fn to_screen(self, v: Vector) -> Position {
+        (v - self.position).into()
+    }
+}
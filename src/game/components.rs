@@ -0,0 +1,33 @@
+//! Components the engine itself knows about. Game code can store any other
+//! `'static` type through `World::insert`.
+
+use std::rc::Rc;
+
+use ::math::Vector;
+use ::gfx::image::ImageDelegate;
+use ::game::world::{Thinker, Drawer, AnimationCallback};
+
+/// Position in world space, as opposed to the screen pixels of `math::Position`.
+pub struct WorldPosition(pub Vector);
+pub struct Velocity(pub Vector);
+pub struct ThinkerRef(pub Rc<Thinker>);
+pub struct DrawerRef(pub Rc<Drawer>);
+pub struct SpriteImage(pub ImageDelegate);
+
+/// Added to velocity every frame before it is applied.
+pub struct Acceleration(pub Vector);
+/// Fraction of velocity lost each frame, from 0.0 to 1.0.
+pub struct Friction(pub f32);
+/// Upper bound on the length of velocity.
+pub struct MaxSpeed(pub f32);
+
+/// Draw order for the default drawer. Higher priorities are drawn on top.
+#[derive(Copy, Clone)]
+pub struct SpritePriority {
+    pub priority: i32,
+    pub behind_background: bool
+}
+
+/// Called with the clip name whenever the entity's `Animation` completes a
+/// clip.
+pub struct OnAnimationFinish(pub Rc<AnimationCallback>);
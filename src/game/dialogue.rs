@@ -0,0 +1,492 @@
+//! Text boxes with a typewriter effect, drawn on the window layer.
+//!
+//! Page text may contain `{wait:N}` to pause for N frames and, at the end of
+//! a page, `{choice:Yes|No}` to ask the player to pick an option. Pages too
+//! long for the box are shown a boxful at a time, keeping room for the
+//! choices on the last one. Choices that don't fit in the box scroll.
+
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use ::input::{InputState, PressedState};
+use ::game::world::World;
+use ::gfx::screen::Screen;
+use ::gfx::image::{Image, TRANSPARENT};
+use ::gfx::font::{Font, Align};
+use ::gfx::blit::BlitOptions;
+use ::math::rect::Rect;
+
+/// Called when a dialogue closes with the choice made on each page that had
+/// one, in order.
+pub type DialogueCallback = Fn(Rc<RefCell<World>>, &[usize]) -> ();
+
+const PADDING: i32 = 6;
+const BORDER_SHADE: u8 = 0;
+const FILL_SHADE: u8 = 3;
+
+struct Page {
+    /// The text with markup removed.
+    text: String,
+    /// (char index into `text`, frames) pairs.
+    waits: Vec<(usize, u32)>,
+    choices: Vec<String>
+}
+
+impl Page {
+    fn parse(source: &str) -> Page {
+        let mut page = Page {
+            text: String::new(),
+            waits: Vec::new(),
+            choices: Vec::new()
+        };
+
+        let mut rest = source;
+        while let Some(open) = rest.find('{') {
+            page.text.push_str(&rest[..open]);
+            let close = match rest[open..].find('}') {
+                Some(c) => open + c,
+                None => {
+                    rest = &rest[open..];
+                    break;
+                }
+            };
+
+            let tag = &rest[open + 1..close];
+            if tag.starts_with("wait:") {
+                match tag[5..].trim().parse() {
+                    Ok(frames) => page.waits.push((page.text.chars().count(), frames)),
+                    Err(_) => warn!("Bad dialogue wait \"{}\"", tag)
+                }
+            } else if tag.starts_with("choice:") {
+                page.choices = tag[7..].split('|').map(|c| c.trim().to_string()).collect();
+            } else {
+                warn!("Unknown dialogue tag \"{}\"", tag);
+            }
+            rest = &rest[close + 1..];
+        }
+        page.text.push_str(rest);
+        page
+    }
+}
+
+pub struct Dialogue {
+    pages: Vec<Page>,
+    font: Rc<Font>,
+    /// Where the box is drawn on the window layer.
+    pub rect: Rect,
+    /// Frames between revealed characters.
+    pub speed: u32,
+    pub on_close: Option<Rc<DialogueCallback>>,
+
+    page: usize,
+    /// Characters of the wrapped page revealed so far.
+    revealed: usize,
+    /// First wrapped line in the box.
+    top: usize,
+    timer: u32,
+    selected: usize,
+    /// First choice in the box.
+    choice_top: usize,
+    chosen: Vec<usize>,
+    lines: Vec<String>,
+    /// Wrapped character index and frames for each wait on the page.
+    waits: Vec<(usize, u32)>,
+    window_was_visible: bool,
+    shown: bool,
+    closed: bool
+}
+
+impl Dialogue {
+    /// A dialogue in the bottom third of the screen, one page per string.
+    pub fn new(font: Rc<Font>, pages: &[&str]) -> Dialogue {
+        let mut d = Dialogue {
+            pages: pages.iter().map(|p| Page::parse(p)).collect(),
+            font: font,
+            rect: Rect::new(0, 96, 160, 48),
+            speed: 2,
+            on_close: None,
+            page: 0,
+            revealed: 0,
+            top: 0,
+            timer: 0,
+            selected: 0,
+            choice_top: 0,
+            chosen: Vec::new(),
+            lines: Vec::new(),
+            waits: Vec::new(),
+            window_was_visible: false,
+            shown: false,
+            closed: false
+        };
+        d.start_page();
+        d
+    }
+
+    #[inline]
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Choices made so far, one per page with a choice.
+    pub fn choices(&self) -> &[usize] {
+        &self.chosen
+    }
+
+    fn text_rect(&self) -> Rect {
+        Rect::new(self.rect.x() + PADDING, self.rect.y() + PADDING,
+                  self.rect.w().saturating_sub(PADDING as u32 * 2), self.rect.h().saturating_sub(PADDING as u32 * 2))
+    }
+
+    /// Wrap the current page and move its waits onto the wrapped text.
+    fn start_page(&mut self) -> () {
+        self.revealed = 0;
+        self.top = 0;
+        self.timer = 0;
+        self.selected = 0;
+        self.choice_top = 0;
+        self.lines.clear();
+        self.waits.clear();
+
+        let page = match self.pages.get(self.page) {
+            Some(p) => p,
+            None => return
+        };
+        self.lines = self.font.wrap(&page.text, self.text_rect().w());
+
+        // Wrapping only drops characters (spaces at breaks and newlines), so
+        // walk both texts together to line the indices up.
+        let wrapped: Vec<char> = self.lines.iter().flat_map(|l| l.chars()).collect();
+        let mut j = 0;
+        let mut plain_to_wrapped = Vec::new();
+        for c in page.text.chars() {
+            plain_to_wrapped.push(j);
+            if j < wrapped.len() && wrapped[j] == c {
+                j += 1;
+            }
+        }
+        plain_to_wrapped.push(j);
+        for &(i, frames) in page.waits.iter() {
+            self.waits.push((plain_to_wrapped[::std::cmp::min(i, plain_to_wrapped.len() - 1)], frames));
+        }
+    }
+
+    /// Characters in the first `n` wrapped lines.
+    fn length_of(&self, n: usize) -> usize {
+        self.lines.iter().take(n).map(|l| l.chars().count()).sum()
+    }
+
+    /// Lines of text that fit in the box.
+    fn capacity(&self) -> usize {
+        let line_height = ::std::cmp::max(self.font.line_height, 1);
+        ::std::cmp::max((self.text_rect().h() / line_height) as usize, 1)
+    }
+
+    /// Lines given to choices on the last boxful of a page.
+    fn choice_rows(&self) -> usize {
+        ::std::cmp::min(self.choices_on_page().len(), self.capacity())
+    }
+
+    /// The line after the last one in the box. The last boxful of a page
+    /// leaves a line for each choice that fits, so earlier ones take up the
+    /// slack.
+    fn bottom(&self) -> usize {
+        let capacity = self.capacity();
+        let room = capacity - self.choice_rows();
+        let remaining = self.lines.len() - self.top;
+        if remaining <= room {
+            self.lines.len()
+        } else {
+            self.top + ::std::cmp::min(capacity, remaining - room)
+        }
+    }
+
+    fn box_done(&self) -> bool {
+        self.revealed >= self.length_of(self.bottom())
+    }
+
+    /// The whole page has been shown, and the box has room for its choices.
+    fn page_done(&self) -> bool {
+        let bottom = self.bottom();
+        self.box_done() && bottom == self.lines.len() && self.capacity() - (bottom - self.top) >= self.choice_rows()
+    }
+
+    fn choices_on_page(&self) -> &[String] {
+        match self.pages.get(self.page) {
+            Some(p) => &p.choices[..],
+            None => &[]
+        }
+    }
+
+    fn next_page(&mut self) -> () {
+        self.page += 1;
+        if self.page >= self.pages.len() {
+            self.closed = true;
+        } else {
+            self.start_page();
+        }
+    }
+
+    /// Advance the typewriter and react to the A and B buttons.
+    pub fn update(&mut self, input: &InputState) -> () {
+        if self.closed {
+            return;
+        }
+
+        let a = input.a == PressedState::Pressed;
+        let b = input.b != PressedState::Up;
+
+        if !self.box_done() {
+            if b || a {
+                self.revealed = self.length_of(self.bottom());
+                return;
+            }
+
+            if self.timer > 0 {
+                self.timer -= 1;
+                return;
+            }
+            self.revealed += 1;
+            self.timer = self.speed.saturating_sub(1);
+            for &(i, frames) in self.waits.iter() {
+                if i == self.revealed {
+                    self.timer += frames;
+                }
+            }
+            return;
+        }
+
+        if !self.page_done() {
+            if a {
+                self.top = self.bottom();
+            }
+            return;
+        }
+
+        let choice_count = self.choices_on_page().len();
+        if choice_count > 0 {
+            if input.up == PressedState::Pressed && self.selected > 0 {
+                self.selected -= 1;
+            }
+            if input.down == PressedState::Pressed && self.selected + 1 < choice_count {
+                self.selected += 1;
+            }
+            let rows = self.choice_rows();
+            if self.selected < self.choice_top {
+                self.choice_top = self.selected;
+            } else if self.selected >= self.choice_top + rows {
+                self.choice_top = self.selected + 1 - rows;
+            }
+            if a {
+                let selected = self.selected;
+                self.chosen.push(selected);
+                self.next_page();
+            }
+        } else if a {
+            self.next_page();
+        }
+    }
+
+    /// Clear the box from the window layer, hiding the window again if it
+    /// was hidden before the dialogue opened.
+    pub fn close(&self, screen: &mut Screen) -> () {
+        if !self.shown {
+            return;
+        }
+        fill(&mut screen.window.image, self.rect, TRANSPARENT);
+        screen.window.visible = self.window_was_visible;
+    }
+
+    /// Draw the box and revealed text onto the window layer, showing the
+    /// window the first time.
+    pub fn draw(&mut self, screen: &mut Screen) -> () {
+        if !self.shown {
+            self.window_was_visible = screen.window.visible;
+            screen.window.visible = true;
+            self.shown = true;
+        }
+
+        let target = &mut screen.window.image;
+        draw_box(target, self.rect);
+
+        let options = BlitOptions::new();
+        let text = self.text_rect();
+        let bottom = self.bottom();
+        let mut left = self.revealed - self.length_of(self.top);
+        let mut shown = Vec::new();
+        for line in self.lines[self.top..bottom].iter() {
+            let n = line.chars().count();
+            if left >= n {
+                shown.push(line.clone());
+                left -= n;
+            } else {
+                shown.push(line.chars().take(left).collect());
+                break;
+            }
+        }
+        self.font.draw_lines(target, &shown, text, Align::Left, &options);
+
+        if self.page_done() {
+            let line_height = self.font.line_height as i32;
+            let mut y = text.y() + (bottom - self.top) as i32 * line_height;
+            let choices = self.choices_on_page().iter().enumerate().skip(self.choice_top).take(self.choice_rows());
+            for (i, choice) in choices {
+                if i == self.selected {
+                    self.font.draw_line(target, ">", text.x(), y, text, &options);
+                }
+                let indent = self.font.measure("> ") as i32;
+                self.font.draw_line(target, choice, text.x() + indent, y, text, &options);
+                y += line_height;
+            }
+        }
+    }
+}
+
+/// Draw the dialogue box frame and background.
+pub fn draw_box(target: &mut Image, r: Rect) -> () {
+    fill(target, r, BORDER_SHADE);
+    fill(target, Rect::new(r.x() + 1, r.y() + 1, r.w().saturating_sub(2), r.h().saturating_sub(2)), FILL_SHADE);
+    fill(target, Rect::new(r.x() + 2, r.y() + 2, r.w().saturating_sub(4), r.h().saturating_sub(4)), BORDER_SHADE);
+    fill(target, Rect::new(r.x() + 3, r.y() + 3, r.w().saturating_sub(6), r.h().saturating_sub(6)), FILL_SHADE);
+}
+
+pub fn fill(target: &mut Image, rect: Rect, shade: u8) -> () {
+    let size = target.size();
+    let r = rect.clip(&Rect::new(0, 0, size.width, size.height));
+    for y in r.y()..r.max_y() {
+        for x in r.x()..r.max_x() {
+            let _ = target.set_pixel((x as u32, y as u32), shade);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::{Dialogue, Page};
+    use ::input::{InputState, PressedState, Button};
+    use ::gfx::font::Font;
+    use ::math::rect::Rect;
+    use ::math::size::Size;
+    use ::testing::{solid, press};
+
+    /// A dialogue whose text area fits `cols` characters by `rows` lines.
+    fn dialogue(pages: &[&str], cols: u32, rows: u32) -> Dialogue {
+        let font = Font::fixed(solid(4 * 95, 8, 0), Size::new(4, 8), ' ');
+        let mut d = Dialogue::new(Rc::new(font), pages);
+        d.rect = Rect::new(0, 0, cols * 4 + 12, rows * 8 + 12);
+        d.speed = 1;
+        d.start_page();
+        d
+    }
+
+    #[test]
+    fn parse_markup() {
+        let page = Page::parse("Hi{wait:10} there{choice: Yes | No}");
+        assert_eq!(page.text, "Hi there");
+        assert_eq!(page.waits, vec![(2, 10)]);
+        assert_eq!(page.choices, vec!["Yes", "No"]);
+
+        let page = Page::parse("a{wait:x}b{foo}c{open");
+        assert_eq!(page.text, "abc{open");
+        assert!(page.waits.is_empty() && page.choices.is_empty());
+    }
+
+    #[test]
+    fn waits_follow_wrapping() {
+        let d = dialogue(&["aaa bbb {wait:5}ccc"], 7, 3);
+        assert_eq!(d.lines, vec!["aaa bbb", "ccc"]);
+        assert_eq!(d.waits, vec![(7, 5)]);
+
+        let d = dialogue(&["ab\n{wait:3}cd"], 7, 3);
+        assert_eq!(d.waits, vec![(2, 3)]);
+    }
+
+    #[test]
+    fn typewriter_pauses_at_waits() {
+        let mut d = dialogue(&["ab{wait:2}c"], 10, 3);
+        let mut revealed = Vec::new();
+        for _ in 0..5 {
+            d.update(&InputState::new());
+            revealed.push(d.revealed);
+        }
+        assert_eq!(revealed, vec![1, 2, 2, 2, 3]);
+        assert!(d.page_done());
+    }
+
+    #[test]
+    fn a_skips_then_turns_the_page() {
+        let mut d = dialogue(&["hello", "bye"], 10, 3);
+        d.update(&press(&[Button::A]));
+        assert!(d.page_done());
+        d.update(&press(&[Button::A]));
+        assert_eq!((d.page, d.revealed), (1, 0));
+
+        // Holding B reveals but never turns the page.
+        let mut held = InputState::new();
+        held.b = PressedState::Held;
+        d.update(&held);
+        d.update(&held);
+        assert!(d.page_done() && !d.is_closed());
+        d.update(&press(&[Button::A]));
+        assert!(d.is_closed());
+    }
+
+    #[test]
+    fn choices_move_and_are_recorded() {
+        let mut d = dialogue(&["Go?{choice:Yes|No|Maybe}", "Ok{choice:A|B}"], 10, 4);
+        d.update(&press(&[Button::A]));
+        d.update(&press(&[Button::Up]));
+        assert_eq!(d.selected, 0);
+        for _ in 0..3 {
+            d.update(&press(&[Button::Down]));
+        }
+        assert_eq!(d.selected, 2);
+        d.update(&press(&[Button::Up]));
+        d.update(&press(&[Button::A]));
+        assert_eq!((d.page, d.selected), (1, 0));
+
+        d.update(&press(&[Button::A]));
+        d.update(&press(&[Button::A]));
+        assert!(d.is_closed());
+        assert_eq!(d.choices(), &[1, 0]);
+    }
+
+    #[test]
+    fn long_pages_leave_room_for_choices() {
+        let mut d = dialogue(&["aa bb cc dd{choice:y|n}"], 3, 3);
+        assert_eq!(d.lines, vec!["aa", "bb", "cc", "dd"]);
+        assert_eq!(d.bottom(), 3);
+        d.update(&press(&[Button::A]));
+        assert!(!d.page_done());
+        // A without choices waiting moves to the next boxful, not the next page.
+        d.update(&press(&[Button::A]));
+        assert_eq!((d.top, d.bottom()), (3, 4));
+        d.update(&press(&[Button::A]));
+        assert!(d.page_done());
+        d.update(&press(&[Button::Down]));
+        d.update(&press(&[Button::A]));
+        assert_eq!(d.choices(), &[1]);
+    }
+
+    #[test]
+    fn choices_past_the_box_scroll() {
+        let mut d = dialogue(&["hi{choice:a|b|c|d}"], 10, 2);
+        d.update(&press(&[Button::A]));
+        assert!(d.box_done() && !d.page_done());
+        // The choices get a box of their own.
+        d.update(&press(&[Button::A]));
+        assert!(d.page_done());
+        assert_eq!((d.top, d.choice_rows()), (1, 2));
+
+        for _ in 0..3 {
+            d.update(&press(&[Button::Down]));
+        }
+        assert_eq!((d.selected, d.choice_top), (3, 2));
+        d.update(&press(&[Button::Up]));
+        d.update(&press(&[Button::Up]));
+        assert_eq!((d.selected, d.choice_top), (1, 1));
+        d.update(&press(&[Button::A]));
+        assert_eq!(d.choices(), &[1]);
+    }
+}
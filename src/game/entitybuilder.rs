@@ -34,6 +34,13 @@ impl<'a> EntityBuilder<'a> {
         self.i
     }
 
+    /// Attach an arbitrary component.
+    #[inline]
+    pub fn with<T: 'static>(&mut self, component: T) -> &mut Self {
+        self.w.insert(self.i, component);
+        self
+    }
+
     builder_gen_function!(position, set_position, Vector);
     builder_gen_function!(velocity, set_velocity, Vector);
     builder_gen_function!(thinker, set_thinker, Rc<Thinker>);
@@ -1,204 +1,433 @@
-extern crate sdl2;
-
 pub mod world;
 pub mod entitybuilder;
+pub mod components;
+pub mod systems;
+pub mod camera;
+pub mod dialogue;
+pub mod scene;
+pub mod timing;
+pub mod rebind;
 
 use std::rc::Rc;
-use std::cell::RefCell;
-use std::path::PathBuf;
+use std::cell::{Cell, RefCell};
 
-use sdl2::EventPump;
-use sdl2::render::Renderer;
-use sdl2::Sdl;
-use sdl2::VideoSubsystem;
+use sdl2::keyboard::Scancode;
 
-use ::input::{InputState, PressedState};
+use ::backend::{Backend, Event};
+use ::input::{InputState, PressedState, KeyBindings};
 use ::gfx::screen::Screen;
-use ::math::rect::Rect;
-use ::game::world::World;
-use ::gfx::blit::Blit;
-
-pub struct System<'a> {
-    pub sdl: Sdl,
-    pub video_subsystem: VideoSubsystem,
-    pub renderer: Renderer<'a>
-}
-
-impl<'a> System<'a> {
-    pub fn new(title: &str) -> Result<System<'a>, String> {
-        let sdl = try!(sdl2::init());
-        let video = try!(sdl.video());
-        let mut window_builder = video.window(title, 640, 576);
-        let window = try!(window_builder.position_centered().resizable().build());
-        let renderer = try!(window.renderer().build());
-
-        Ok(System {
-            sdl: sdl,
-            video_subsystem: video,
-            renderer: renderer
-        })
-    }
-}
+use ::gfx::palettes::Palette;
+use ::gfx::effects::PaletteEffect;
+use ::game::scene::{Scene, SceneChange, Transition};
+use ::game::timing::{Timing, FrameStats, Accumulator};
 
-pub struct Game<'a> {
-    pub system: System<'a>,
+pub struct Game<B: Backend> {
+    pub backend: B,
     pub input_state: InputState,
     pub running: bool,
     pub screen: Rc<RefCell<Screen>>,
-    pub world: Option<Rc<RefCell<World>>>
+    /// Shared so scenes like `RebindScene` can change them.
+    pub bindings: Rc<RefCell<KeyBindings>>,
+    /// Keys down now, so a button stays down while any of its keys is.
+    held_keys: Vec<Scancode>,
+    /// Step rate and frame skip limit, read when `run` starts.
+    pub timing: Timing,
+    /// Measured frame pacing, shared so scenes can show it.
+    pub stats: Rc<Cell<FrameStats>>,
+    /// Palettes the player can cycle through with P.
+    pub palettes: Vec<Palette>,
+    palette: usize,
+    /// The scene stack; the last scene is on top and the only one updated.
+    scenes: Vec<Box<Scene>>,
+    /// A change waiting for its fade out to finish.
+    pending: Option<SceneChange>,
+    fading_in: bool
 }
 
-impl<'a> Game<'a> {
-    pub fn new() -> Result<Game<'a>, String> {
-        Ok(Game {
-            system: try!(System::new("gbjam4")),
+impl<B: Backend> Game<B> {
+    pub fn new(backend: B) -> Game<B> {
+        Game {
+            backend: backend,
             input_state: InputState::new(),
             running: true,
             screen: Rc::new(RefCell::new(Screen::new())),
-            world: None
-        })
+            bindings: Rc::new(RefCell::new(KeyBindings::new())),
+            held_keys: Vec::new(),
+            timing: Timing::new(),
+            stats: Rc::new(Cell::new(FrameStats::new())),
+            palettes: ::gfx::palettes::presets(),
+            palette: 0,
+            scenes: Vec::new(),
+            pending: None,
+            fading_in: false
+        }
     }
 
-    pub fn run(&mut self) -> Result<(), String> {
-        use sdl2::pixels::PixelFormatEnum;
-        use sdl2::render::Texture;
-        use ::gfx::image::Image;
-
-        // loop over events
-        let mut event_pump: EventPump = match self.system.sdl.event_pump() {
-            Ok(e) => e,
-            Err(s) => return Err(s)
-        };
+    /// Measured frame pacing so far.
+    #[inline]
+    pub fn stats(&self) -> FrameStats {
+        self.stats.get()
+    }
 
-        let mut path_buf = PathBuf::new();
-        path_buf.push("assets");
-        path_buf.push("test-img.png");
-        let im = try!(::assets::load_image(path_buf));
+    fn update_stats<F: FnOnce(&mut FrameStats)>(&self, f: F) -> () {
+        let mut stats = self.stats.get();
+        f(&mut stats);
+        self.stats.set(stats);
+    }
 
-        // Set screen colors
-        self.screen.borrow_mut().colors = ::gfx::palettes::default_colors();
+    /// Index into `palettes` of the one on screen.
+    #[inline]
+    pub fn palette(&self) -> usize {
+        self.palette
+    }
+
+    /// Show the palette at `index`, wrapping around the list.
+    pub fn set_palette(&mut self, index: usize) -> () {
+        if self.palettes.is_empty() {
+            return;
+        }
+        self.palette = index % self.palettes.len();
+        let palette = &self.palettes[self.palette];
+        self.screen.borrow_mut().colors = palette.colors;
+        info!("Palette: {}", palette.name);
+    }
 
-        //self.system.renderer.set_clip_rect()
+    pub fn next_palette(&mut self) -> () {
+        let next = self.palette + 1;
+        self.set_palette(next);
+    }
+
+    /// Put a scene on top of the stack straight away, pausing the current
+    /// top scene.
+    pub fn push_scene(&mut self, scene: Box<Scene>) -> () {
+        self.apply_change(SceneChange::Push(scene, Transition::Cut));
+    }
+
+    /// Carry out a scene change, after a fade out if it asks for one. Changes
+    /// requested while another is fading out are ignored.
+    pub fn change_scene(&mut self, change: SceneChange) -> () {
+        if self.pending.is_some() {
+            return;
+        }
+        let transition = match change {
+            SceneChange::Continue => return,
+            SceneChange::Quit => {
+                self.running = false;
+                return;
+            },
+            SceneChange::Push(_, t) | SceneChange::Pop(t) | SceneChange::Replace(_, t) => t
+        };
+        match transition {
+            Transition::Cut => self.apply_change(change),
+            Transition::Fade(tone, frames) => {
+                self.screen.borrow_mut().start_effect(PaletteEffect::fade_out(tone, frames));
+                self.pending = Some(change);
+            }
+        }
+    }
 
-        let mut render_texture: Texture = try!(self.system.renderer.create_texture_streaming(PixelFormatEnum::RGB888, (160, 144)));
+    fn apply_change(&mut self, change: SceneChange) -> () {
+        match change {
+            SceneChange::Push(mut scene, _) => {
+                if let Some(top) = self.scenes.last_mut() {
+                    top.pause(&self.screen);
+                }
+                scene.enter(&self.screen);
+                self.scenes.push(scene);
+            },
+            SceneChange::Pop(_) => {
+                if let Some(mut top) = self.scenes.pop() {
+                    top.exit(&self.screen);
+                }
+                if let Some(top) = self.scenes.last_mut() {
+                    top.resume(&self.screen);
+                }
+            },
+            SceneChange::Replace(mut scene, _) => {
+                if let Some(mut top) = self.scenes.pop() {
+                    top.exit(&self.screen);
+                }
+                scene.enter(&self.screen);
+                self.scenes.push(scene);
+            },
+            SceneChange::Continue => (),
+            SceneChange::Quit => { self.running = false; }
+        }
+    }
 
-        try!(self.system.renderer.set_logical_size(160u32, 144u32));
+    /// Once a fade out is done, switch scenes and fade back in.
+    fn finish_transition(&mut self) -> () {
+        if self.screen.borrow().effect().is_some() {
+            return;
+        }
+        self.fading_in = false;
 
-        // Create world
-        self.world = Some(Rc::new(RefCell::new(World::new())));
+        let change = match self.pending.take() {
+            Some(c) => c,
+            None => return
+        };
+        let transition = match change {
+            SceneChange::Push(_, t) | SceneChange::Pop(t) | SceneChange::Replace(_, t) => t,
+            _ => Transition::Cut
+        };
+        self.apply_change(change);
+        if let Transition::Fade(tone, frames) = transition {
+            self.screen.borrow_mut().start_effect(PaletteEffect::fade_in(tone, frames));
+            self.fading_in = true;
+        }
+    }
 
-        // Make an entity
-        if let Some(ref mut w) = self.world {
-            use std::ops::DerefMut;
-            use ::game::entitybuilder::EntityBuilder;
-            use ::math::Vector;
-            use ::gfx::image::ImageDelegate::*;
+    /// Draw the top scene, and the scenes under it while it's an overlay.
+    fn draw_scenes(&mut self) -> () {
+        let mut start = self.scenes.len();
+        while start > 0 {
+            start -= 1;
+            if !self.scenes[start].is_overlay() {
+                break;
+            }
+        }
+        for scene in self.scenes[start..].iter_mut() {
+            scene.draw(&self.screen);
+        }
+    }
 
-            let mut world = w.borrow_mut();
-            let world_ref: &mut World = world.deref_mut();
-            let ent = EntityBuilder::new(world_ref)
-                .position(Vector::new(0.0, 0.0))
-                .sprite(ImageBuf(Rc::new(im)))
-                .finish();
+    /// One fixed step of game logic.
+    fn tick(&mut self) -> () {
+        self.input_state.update();
+        self.handle_events();
+        if !self.running {
+            return;
         }
 
+        if self.pending.is_none() && !self.fading_in {
+            let input = self.input_state;
+            let change = match self.scenes.last_mut() {
+                Some(scene) => scene.update(input, &self.screen),
+                None => SceneChange::Continue
+            };
+            self.change_scene(change);
+        }
+
+        let finished = self.screen.borrow_mut().update_effect();
+        if let Some(mut callback) = finished {
+            callback();
+        }
+        self.finish_transition();
+        self.update_stats(|s| s.updates += 1);
+    }
+
+    fn render(&mut self) -> Result<(), String> {
+        self.screen.borrow_mut().begin_frame();
+        self.draw_scenes();
+        self.screen.borrow_mut().composite();
+        try!(self.backend.present(&self.screen.borrow()));
+        self.update_stats(|s| s.frames += 1);
+        Ok(())
+    }
+
+    /// Run until quit or the last scene is popped. Logic steps at
+    /// `timing.rate` whatever the display does; a frame is drawn after each
+    /// batch of steps, or every loop with vsync.
+    pub fn run(&mut self) -> Result<(), String> {
+        // Set screen colors
+        let palette = self.palette;
+        self.set_palette(palette);
+
+        let mut accumulator = Accumulator::new(self.timing);
+        let mut previous = self.backend.time();
+
         // Play. The. Game.
-        while self.running {
-            use std::thread;
-            self.input_state.update();
-
-            self.handle_events(&mut event_pump);
-
-            // think and draw entities
-            if let Some(ref mut w) = self.world {
-                let entities_clone = w.borrow().clone_entities();
-                for i in entities_clone.into_iter() {
-                    if let Some(thinker) = w.borrow().thinker(i) {
-                        thinker(w.clone(), i, self.input_state);
-                    }
-                }
-                let entities_clone = w.borrow().clone_entities();
-
-                for i in entities_clone.into_iter() {
-                    if let Some(drawer) = w.borrow().drawer(i) {
-                        drawer(w.clone(), self.screen.clone(), i);
-                    } else {
-                        // default drawer implementation
-                        if let Some(sprite) = w.borrow().sprite(i) {
-                            sprite.blit_to(None, &mut self.screen.borrow_mut().image, None);
-                        }
-                    }
-                }
+        while self.running && !self.scenes.is_empty() {
+            let now = self.backend.time();
+            let elapsed = now.saturating_sub(previous);
+            previous = now;
+            if self.stats().updates > 0 {
+                self.update_stats(|s| s.record_frame_time(elapsed));
             }
+            accumulator.add(elapsed);
 
-            // copy custom screen buffer to render texture, mapping colors
-            render_texture.with_lock(None, |buf, size| {
-                let screen_b = self.screen.borrow_mut();
-                for (i, x) in screen_b.image.buffer.iter().enumerate() {
-                    let color = &screen_b.colors[*x as usize];
-                    // It's BGR for some reason?
-                    buf[(i * 4) + 0] = color[2];
-                    buf[(i * 4) + 1] = color[1];
-                    buf[(i * 4) + 2] = color[0];
+            let (steps, dropped) = accumulator.take_steps();
+            self.update_stats(|s| s.dropped += dropped);
+            for _ in 0..steps {
+                if !self.running || self.scenes.is_empty() {
+                    break;
                 }
-                ()
-            }).unwrap();
+                self.tick();
+            }
 
-            self.system.renderer.clear();
-            self.system.renderer.copy(&render_texture, None, None);
-            self.system.renderer.present();
+            if !self.running {
+                break;
+            }
+            let vsync = self.backend.vsync();
+            if steps > 0 || vsync {
+                try!(self.render());
+            }
+            if !vsync {
+                let spent = self.backend.time().saturating_sub(now);
+                let wait = accumulator.until_next().saturating_sub(spent);
+                if wait > 0 {
+                    self.backend.sleep(wait);
+                }
+            }
+        }
 
-            thread::sleep_ms((1000 / 60) as u32);
+        while let Some(mut scene) = self.scenes.pop() {
+            scene.exit(&self.screen);
         }
 
         Ok(())
     }
 
-    fn handle_events(&mut self, event_pump: &mut EventPump) -> () {
-        for e in event_pump.poll_iter() {
-            use sdl2::event::Event::*;
+    fn handle_events(&mut self) -> () {
+        for e in self.backend.poll_events() {
             match e {
-                Quit { .. } => { self.running = false; }
-                KeyUp { scancode, .. } => {
-                    scancode.map(|s| { self.handle_key_up(s) });
-                },
-                KeyDown { scancode, .. } => {
-                    scancode.map(|s| { self.handle_key_down(s) });
-                },
-                _ => (),
+                Event::Quit => { self.running = false; }
+                Event::KeyUp(s) => self.handle_key_up(s),
+                Event::KeyDown(s) => self.handle_key_down(s)
             };
         };
     }
 
-    fn handle_key_up(&mut self, scancode: sdl2::keyboard::Scancode) -> () {
-        use self::sdl2::keyboard::Scancode;
+    fn handle_key_up(&mut self, scancode: Scancode) -> () {
+        self.held_keys.retain(|&s| s != scancode);
+        let bindings = self.bindings.borrow();
+        if let Some(button) = bindings.button(scancode) {
+            if !bindings.keys(button).iter().any(|k| self.held_keys.contains(k)) {
+                self.input_state.set(button, PressedState::Up);
+            }
+        }
+    }
 
-        match scancode {
-            Scancode::Left => { self.input_state.left = PressedState::Up },
-            Scancode::Right => { self.input_state.right = PressedState::Up },
-            Scancode::Up => {self.input_state.up = PressedState::Up},
-            Scancode::Down => {self.input_state.down = PressedState::Up},
-            Scancode::Z => {self.input_state.a = PressedState::Up},
-            Scancode::X => {self.input_state.b = PressedState::Up},
-            Scancode::Return => {self.input_state.start = PressedState::Up},
-            Scancode::RShift => {self.input_state.select = PressedState::Up},
-            _ => return ()
+    fn handle_key_down(&mut self, scancode: Scancode) -> () {
+        if self.bindings.borrow_mut().capture_key(scancode) {
+            return;
+        }
+        if !self.held_keys.contains(&scancode) {
+            self.held_keys.push(scancode);
+        }
+        let (button, palette) = {
+            let bindings = self.bindings.borrow();
+            (bindings.button(scancode), bindings.is_palette_key(scancode))
         };
+        match button {
+            // A second key for a button already down doesn't press it again.
+            Some(b) if self.input_state.get(b) == PressedState::Up => self.input_state.set(b, PressedState::Pressed),
+            Some(_) => (),
+            None if palette => self.next_palette(),
+            None => ()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use std::cell::{Cell, RefCell};
+
+    use super::Game;
+    use ::backend::{Event, Scancode};
+    use ::backend::headless::HeadlessBackend;
+    use ::game::scene::{Scene, SceneChange, Transition};
+    use ::game::timing::{Timing, FrameStats};
+    use ::gfx::screen::Screen;
+    use ::gfx::effects::Tone;
+    use ::input::{InputState, Button};
+    use ::testing::{Recorder, log};
+
+    #[test]
+    fn scene_stack_order_and_fades() {
+        let log = log();
+        let top = Recorder::new("b", &log, vec![SceneChange::Continue, SceneChange::Pop(Transition::Fade(Tone::Dark, 2))]);
+        let bottom = Recorder::new("a", &log, vec![SceneChange::Push(top, Transition::Cut)]);
+
+        let mut game = Game::new(HeadlessBackend::new(Some(8)));
+        game.push_scene(bottom);
+        game.run().unwrap();
+
+        // Two frames fading out and two fading back in get no updates.
+        let expected = ["a enter",
+                        "a update", "a pause", "b enter",
+                        "b update",
+                        "b update",
+                        "b exit", "a resume",
+                        "a update",
+                        "a update",
+                        "a exit"];
+        assert_eq!(*log.borrow(), expected.iter().map(|s| s.to_string()).collect::<Vec<_>>());
     }
 
-    fn handle_key_down(&mut self, scancode: sdl2::keyboard::Scancode) -> () {
-        use self::sdl2::keyboard::Scancode;
+    #[test]
+    fn replace_and_popping_the_last_scene() {
+        let log = log();
+        let next = Recorder::new("b", &log, vec![SceneChange::Pop(Transition::Cut)]);
+        let first = Recorder::new("a", &log, vec![SceneChange::Replace(next, Transition::Cut)]);
 
-        match scancode {
-            Scancode::Left => { self.input_state.left = PressedState::Pressed },
-            Scancode::Right => { self.input_state.right = PressedState::Pressed },
-            Scancode::Up => {self.input_state.up = PressedState::Pressed},
-            Scancode::Down => {self.input_state.down = PressedState::Pressed},
-            Scancode::Z => {self.input_state.a = PressedState::Pressed},
-            Scancode::X => {self.input_state.b = PressedState::Pressed},
-            Scancode::Return => {self.input_state.start = PressedState::Pressed},
-            Scancode::RShift => {self.input_state.select = PressedState::Pressed},
-            _ => return ()
-        };
+        let mut game = Game::new(HeadlessBackend::new(Some(10)));
+        game.push_scene(first);
+        game.run().unwrap();
+
+        let expected = ["a enter", "a update", "a exit", "b enter", "b update", "b exit"];
+        assert_eq!(*log.borrow(), expected.iter().map(|s| s.to_string()).collect::<Vec<_>>());
+        assert_eq!(game.backend.frames_presented, 2);
+    }
+
+    #[test]
+    fn captured_keys_are_not_held_and_palette_keys_cycle() {
+        let mut backend = HeadlessBackend::new(Some(3));
+        backend.push_event(1, Event::KeyDown(Scancode::C));
+        backend.push_event(2, Event::KeyDown(Scancode::F1));
+        let mut game = Game::new(backend);
+        game.bindings.borrow_mut().bind_palette(Scancode::F1);
+        game.bindings.borrow_mut().capture(Button::A);
+        game.push_scene(Recorder::new("a", &log(), Vec::new()));
+        game.run().unwrap();
+
+        assert_eq!(game.bindings.borrow().keys(Button::A), vec![Scancode::C]);
+        assert_eq!(game.held_keys, vec![Scancode::F1]);
+        assert_eq!(game.palette(), 1);
+    }
+
+    /// Records the updates counted before each of its own updates.
+    struct Counter {
+        stats: Rc<Cell<FrameStats>>,
+        seen: Rc<RefCell<Vec<u64>>>
+    }
+
+    impl Scene for Counter {
+        fn update(&mut self, _input: InputState, _screen: &Rc<RefCell<Screen>>) -> SceneChange {
+            self.seen.borrow_mut().push(self.stats.get().updates);
+            SceneChange::Continue
+        }
+    }
+
+    #[test]
+    fn scenes_see_the_stats() {
+        let mut game = Game::new(HeadlessBackend::new(Some(6)));
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        game.push_scene(Box::new(Counter { stats: game.stats.clone(), seen: seen.clone() }));
+        game.run().unwrap();
+
+        let stats = game.stats();
+        assert_eq!((stats.updates, stats.frames, stats.dropped), (6, 6, 0));
+        assert_eq!(stats.frame_time, game.timing.step());
+        assert_eq!(*seen.borrow(), vec![0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn slow_frames_drop_steps() {
+        // Steps of 10ms, at most 3 per frame, and every frame takes 50ms.
+        let mut backend = HeadlessBackend::new(Some(4));
+        backend.present_time = 50_000;
+        let mut game = Game::new(backend);
+        game.timing = Timing { rate: 100.0, max_updates: 3 };
+        game.push_scene(Recorder::new("a", &log(), Vec::new()));
+        game.run().unwrap();
+
+        // One step for the first frame, three for each after, and two dropped
+        // every loop after the first, including the one the quit arrives on.
+        let stats = game.stats();
+        assert_eq!((stats.updates, stats.frames, stats.dropped), (10, 4, 8));
+        assert_eq!(stats.frame_time, 50_000);
+        assert_eq!(stats.fps(), 20.0);
     }
 }
@@ -0,0 +1,118 @@
+//! An overlay that asks for a new key for each button in turn.
+
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::path::PathBuf;
+
+use ::input::{InputState, Button, KeyBindings};
+use ::gfx::screen::Screen;
+use ::gfx::image::TRANSPARENT;
+use ::gfx::font::{Font, Align, draw_text};
+use ::gfx::blit::BlitOptions;
+use ::game::scene::{Scene, SceneChange, Transition};
+use ::game::dialogue::{draw_box, fill};
+use ::math::rect::Rect;
+
+const PADDING: i32 = 6;
+
+/// Steps through every button, binding the next key pressed to it. Escape
+/// puts the old bindings back and leaves. When all buttons are done the
+/// bindings are saved, if a path was given, and the scene pops itself.
+pub struct RebindScene {
+    bindings: Rc<RefCell<KeyBindings>>,
+    previous: KeyBindings,
+    font: Rc<Font>,
+    save_path: Option<PathBuf>,
+    /// Where the box is drawn on the window layer.
+    pub rect: Rect,
+    next: usize,
+    window_was_visible: bool
+}
+
+impl RebindScene {
+    pub fn new(bindings: Rc<RefCell<KeyBindings>>, font: Rc<Font>) -> RebindScene {
+        let previous = bindings.borrow().clone();
+        RebindScene {
+            bindings: bindings,
+            previous: previous,
+            font: font,
+            save_path: None,
+            rect: Rect::new(8, 48, 144, 48),
+            next: 0,
+            window_was_visible: false
+        }
+    }
+
+    /// Save the bindings to `path` once every button is bound.
+    pub fn save_to(mut self, path: PathBuf) -> RebindScene {
+        self.save_path = Some(path);
+        self
+    }
+
+    fn current(&self) -> Option<Button> {
+        Button::all().get(self.next).cloned()
+    }
+}
+
+impl Scene for RebindScene {
+    fn enter(&mut self, screen: &Rc<RefCell<Screen>>) -> () {
+        let mut screen = screen.borrow_mut();
+        self.window_was_visible = screen.window.visible;
+        screen.window.visible = true;
+        if let Some(button) = self.current() {
+            self.bindings.borrow_mut().capture(button);
+        }
+    }
+
+    fn exit(&mut self, screen: &Rc<RefCell<Screen>>) -> () {
+        let mut screen = screen.borrow_mut();
+        fill(&mut screen.window.image, self.rect, TRANSPARENT);
+        screen.window.visible = self.window_was_visible;
+    }
+
+    fn update(&mut self, _input: InputState, _screen: &Rc<RefCell<Screen>>) -> SceneChange {
+        let mut bindings = self.bindings.borrow_mut();
+        if bindings.take_cancelled() {
+            *bindings = self.previous.clone();
+            return SceneChange::Pop(Transition::Cut);
+        }
+        if bindings.capturing().is_some() {
+            return SceneChange::Continue;
+        }
+
+        self.next += 1;
+        match self.current() {
+            Some(button) => {
+                bindings.capture(button);
+                SceneChange::Continue
+            },
+            None => {
+                if let Some(ref path) = self.save_path {
+                    if let Err(e) = ::assets::save_bindings(path.clone(), &bindings) {
+                        warn!("Couldn't save key bindings: {}", e);
+                    }
+                }
+                SceneChange::Pop(Transition::Cut)
+            }
+        }
+    }
+
+    fn draw(&mut self, screen: &Rc<RefCell<Screen>>) -> () {
+        let button = match self.current() {
+            Some(b) => b,
+            None => return
+        };
+        let target = &mut screen.borrow_mut().window.image;
+        draw_box(target, self.rect);
+
+        let r = self.rect;
+        let text = Rect::new(r.x() + PADDING, r.y() + PADDING,
+                             r.w().saturating_sub(PADDING as u32 * 2), r.h().saturating_sub(PADDING as u32 * 2));
+        let prompt = format!("Press a key for {}\nEsc to cancel", button.name().to_uppercase());
+        draw_text(target, &self.font, &prompt, text, Align::Center, &BlitOptions::new());
+    }
+
+    fn is_overlay(&self) -> bool {
+        true
+    }
+}
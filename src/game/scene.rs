@@ -0,0 +1,227 @@
+//! Scenes are the separate states of the game: title screen, level, pause
+//! menu, game over. `Game` keeps them on a stack and only the top one runs.
+
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use ::input::InputState;
+use ::gfx::screen::Screen;
+use ::gfx::effects::Tone;
+use ::gfx::image::ImageDelegate;
+use ::gfx::oam::OamEntry;
+use ::gfx::blit::BlitOptions;
+use ::game::world::{World, EntityID};
+use ::game::components::SpritePriority;
+use ::math::Vector;
+
+/// How the screen changes between scenes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Transition {
+    Cut,
+    /// Fade out to the tone over the given frames, switch, then fade back in
+    /// over the same number of frames.
+    Fade(Tone, u32)
+}
+
+/// What a scene wants the stack to do after its update.
+pub enum SceneChange {
+    Continue,
+    /// Pause this scene and put another on top of it.
+    Push(Box<Scene>, Transition),
+    /// Exit this scene and resume the one below. Popping the last scene ends
+    /// the game.
+    Pop(Transition),
+    /// Exit this scene and put another in its place.
+    Replace(Box<Scene>, Transition),
+    Quit
+}
+
+/// A state of the game. Only `update` is required. The background and window
+/// layers aren't cleared between scenes, so `enter` and `resume` should draw
+/// whatever they need there.
+pub trait Scene {
+    /// Called when the scene is put on the stack.
+    fn enter(&mut self, _screen: &Rc<RefCell<Screen>>) -> () {}
+    /// Called when the scene is taken off the stack.
+    fn exit(&mut self, _screen: &Rc<RefCell<Screen>>) -> () {}
+    /// Called when another scene is pushed on top of this one.
+    fn pause(&mut self, _screen: &Rc<RefCell<Screen>>) -> () {}
+    /// Called when the scene is on top again.
+    fn resume(&mut self, _screen: &Rc<RefCell<Screen>>) -> () {}
+
+    /// Run one frame of logic. Not called during transitions.
+    fn update(&mut self, input: InputState, screen: &Rc<RefCell<Screen>>) -> SceneChange;
+
+    /// Queue sprites and draw into the screen's layers for this frame.
+    fn draw(&mut self, _screen: &Rc<RefCell<Screen>>) -> () {}
+
+    /// Overlays, like a pause menu, have the scene below them drawn first.
+    fn is_overlay(&self) -> bool {
+        false
+    }
+}
+
+/// Decides scene changes for a `WorldScene`, after its world has updated.
+pub type SceneController = FnMut(&Rc<RefCell<World>>, InputState) -> SceneChange;
+
+/// A scene that runs a `World`.
+pub struct WorldScene {
+    pub world: Rc<RefCell<World>>,
+    controller: Option<Box<SceneController>>
+}
+
+impl WorldScene {
+    pub fn new(world: World) -> WorldScene {
+        WorldScene {
+            world: Rc::new(RefCell::new(world)),
+            controller: None
+        }
+    }
+
+    pub fn with_controller<F>(mut self, controller: F) -> WorldScene
+        where F: FnMut(&Rc<RefCell<World>>, InputState) -> SceneChange + 'static {
+        self.controller = Some(Box::new(controller));
+        self
+    }
+}
+
+impl Scene for WorldScene {
+    /// Take an open dialogue's box off the window layer.
+    fn exit(&mut self, screen: &Rc<RefCell<Screen>>) -> () {
+        if let Some(d) = self.world.borrow_mut().take_dialogue() {
+            d.close(&mut screen.borrow_mut());
+        }
+    }
+
+    fn update(&mut self, input: InputState, _screen: &Rc<RefCell<Screen>>) -> SceneChange {
+        update_world(&self.world, input);
+        match self.controller {
+            Some(ref mut c) => c(&self.world, input),
+            None => SceneChange::Continue
+        }
+    }
+
+    fn draw(&mut self, screen: &Rc<RefCell<Screen>>) -> () {
+        draw_world(&self.world, screen);
+    }
+}
+
+/// An OAM entry for an entity drawn by the default drawer, honoring its
+/// `BlitOptions` and `SpritePriority` components.
+fn sprite_entry(world: &World, i: EntityID, image: ImageDelegate, position: Vector) -> OamEntry {
+    let mut entry = OamEntry::new(image, world.camera.to_screen(position));
+    if let Some(options) = world.get::<BlitOptions>(i) {
+        entry.options = *options;
+    }
+    if let Some(p) = world.get::<SpritePriority>(i) {
+        entry.priority = p.priority;
+        entry.behind_background = p.behind_background;
+    }
+    entry
+}
+
+/// One frame of world logic: dialogue, thinkers, movement and animation.
+/// An open dialogue takes the input and pauses everything but animation.
+pub fn update_world(w: &Rc<RefCell<World>>, input: InputState) -> () {
+    use ::game::components::{ThinkerRef, OnAnimationFinish};
+
+    let paused = match w.borrow_mut().dialogue_mut() {
+        Some(d) => {
+            d.update(&input);
+            true
+        },
+        None => false
+    };
+
+    if !paused {
+        // Thinkers may borrow the world, so collect them first.
+        let thinkers: Vec<_> = w.borrow().query::<(ThinkerRef,)>()
+            .map(|(i, (t,))| (i, t.0.clone())).collect();
+        for (i, thinker) in thinkers.into_iter() {
+            if w.borrow().is_alive(i) && !w.borrow().dialogue_open() {
+                thinker(w.clone(), i, input);
+            }
+        }
+        w.borrow_mut().flush_destroyed();
+
+        ::game::systems::movement(&mut w.borrow_mut());
+    }
+
+    let finished = ::game::systems::animate(&mut w.borrow_mut());
+    for (i, clip) in finished.into_iter() {
+        let callback = w.borrow().get::<OnAnimationFinish>(i).map(|c| c.0.clone());
+        if let Some(callback) = callback {
+            callback(w.clone(), i, &clip);
+        }
+    }
+    // Callbacks can queue destroys too; don't draw those entities again.
+    w.borrow_mut().flush_destroyed();
+}
+
+/// Draw a world: its dialogue onto the window layer, then entities through
+/// their drawers or the default one, which queues sprites.
+pub fn draw_world(w: &Rc<RefCell<World>>, screen: &Rc<RefCell<Screen>>) -> () {
+    use ::game::components::{DrawerRef, SpriteImage, WorldPosition};
+    use ::gfx::animation::Animation;
+    use ::gfx::sprite::Sprite;
+
+    let closed = match w.borrow_mut().dialogue_mut() {
+        Some(d) => {
+            let mut screen = screen.borrow_mut();
+            if d.is_closed() {
+                d.close(&mut screen);
+                true
+            } else {
+                d.draw(&mut screen);
+                false
+            }
+        },
+        None => false
+    };
+    if closed {
+        let dialogue = w.borrow_mut().take_dialogue();
+        if let Some(callback) = dialogue.as_ref().and_then(|d| d.on_close.clone()) {
+            callback(w.clone(), dialogue.as_ref().map_or(&[][..], |d| d.choices()));
+        }
+    }
+
+    let drawers: Vec<_> = w.borrow().query::<(DrawerRef,)>()
+        .map(|(i, (d,))| (i, d.0.clone())).collect();
+    for (i, drawer) in drawers.into_iter() {
+        drawer(w.clone(), screen.clone(), i);
+    }
+
+    // default drawer implementation, queued in entity order so ties in
+    // priority are broken the same way every run
+    let world = w.borrow();
+    let mut queued = Vec::new();
+    for (i, (sprite, pos)) in world.query::<(SpriteImage, WorldPosition)>() {
+        if !world.has::<DrawerRef>(i) {
+            queued.push((i, sprite_entry(&world, i, sprite.0.clone(), pos.0)));
+        }
+    }
+    for (i, (animation, pos)) in world.query::<(Animation, WorldPosition)>() {
+        if let (false, Some(frame)) = (world.has::<DrawerRef>(i), animation.current()) {
+            let mut entry = sprite_entry(&world, i, ImageDelegate::ImageRef(frame.image.clone()), pos.0);
+            entry.position = entry.position + frame.offset;
+            queued.push((i, entry));
+        }
+    }
+    for (i, (sprite, pos)) in world.query::<(Sprite, WorldPosition)>() {
+        if world.has::<DrawerRef>(i) {
+            continue;
+        }
+        let mut entry = sprite_entry(&world, i, sprite.image().clone(), pos.0);
+        entry.position = entry.position + sprite.offset();
+        if !world.has::<BlitOptions>(i) {
+            entry.options = sprite.options;
+        }
+        queued.push((i, entry));
+    }
+
+    queued.sort_by_key(|a| a.0.index());
+    let mut screen = screen.borrow_mut();
+    for (_, entry) in queued.into_iter() {
+        screen.sprites.push(entry);
+    }
+}
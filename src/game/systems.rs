@@ -0,0 +1,53 @@
+//! Per-frame steps the engine runs on every world.
+
+use ::game::world::{World, EntityID};
+use ::game::components::*;
+use ::gfx::animation::Animation;
+
+/// Integrates velocity into position for every entity with a `Velocity`.
+/// Units are pixels per frame; `Acceleration`, `Friction` and `MaxSpeed` are
+/// applied to velocity first, in that order.
+pub fn movement(world: &mut World) -> () {
+    let moving: Vec<EntityID> = world.query::<(Velocity,)>().map(|(i, _)| i).collect();
+
+    for i in moving.into_iter() {
+        let mut vel = match world.get::<Velocity>(i) {
+            Some(v) => v.0,
+            None => continue
+        };
+
+        if let Some(a) = world.get::<Acceleration>(i) {
+            vel = vel + a.0;
+        }
+        if let Some(f) = world.get::<Friction>(i) {
+            vel = vel * (1.0 - f.0.max(0.0).min(1.0));
+        }
+        if let Some(m) = world.get::<MaxSpeed>(i) {
+            vel = vel.clamp_length(m.0);
+        }
+
+        if let Some(v) = world.get_mut::<Velocity>(i) {
+            v.0 = vel;
+        }
+        if let Some(p) = world.get_mut::<WorldPosition>(i) {
+            p.0 = p.0 + vel;
+        }
+    }
+}
+
+/// Steps every `Animation` one frame. Returns the entities whose clip
+/// completed, with the clip name, so their callbacks can be run once the
+/// world is no longer borrowed.
+pub fn animate(world: &mut World) -> Vec<(EntityID, String)> {
+    let animated: Vec<EntityID> = world.query::<(Animation,)>().map(|(i, _)| i).collect();
+
+    let mut finished = Vec::new();
+    for i in animated.into_iter() {
+        if let Some(a) = world.get_mut::<Animation>(i) {
+            if a.advance() {
+                finished.push((i, a.current_clip().to_string()));
+            }
+        }
+    }
+    finished
+}
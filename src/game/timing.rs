@@ -0,0 +1,133 @@
+//! Fixed-timestep pacing. Game logic always steps at `Timing::rate`; drawing
+//! happens once per loop after however many steps were due.
+
+/// The DMG's refresh rate in Hz.
+pub const GB_RATE: f64 = 59.73;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Timing {
+    /// Logic steps per second.
+    pub rate: f64,
+    /// Most steps run before drawing. When the game falls further behind,
+    /// the extra time is dropped and it slows down instead of spiralling.
+    pub max_updates: u32
+}
+
+impl Timing {
+    pub fn new() -> Timing {
+        Timing {
+            rate: GB_RATE,
+            max_updates: 4
+        }
+    }
+
+    /// Length of one step in microseconds.
+    #[inline]
+    pub fn step(&self) -> u64 {
+        (1_000_000.0 / self.rate).round() as u64
+    }
+}
+
+/// Measured frame pacing. Times are in microseconds.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FrameStats {
+    /// Time between the starts of the last two loops.
+    pub frame_time: u64,
+    /// Smoothed `frame_time`.
+    pub average_frame_time: f64,
+    /// Frames drawn and presented.
+    pub frames: u64,
+    /// Logic steps run.
+    pub updates: u64,
+    /// Steps dropped because `max_updates` was reached.
+    pub dropped: u64
+}
+
+impl FrameStats {
+    pub fn new() -> FrameStats {
+        FrameStats {
+            frame_time: 0,
+            average_frame_time: 0.0,
+            frames: 0,
+            updates: 0,
+            dropped: 0
+        }
+    }
+
+    /// Measured frames per second.
+    pub fn fps(&self) -> f64 {
+        if self.average_frame_time > 0.0 { 1_000_000.0 / self.average_frame_time } else { 0.0 }
+    }
+
+    pub fn record_frame_time(&mut self, micros: u64) -> () {
+        self.frame_time = micros;
+        self.average_frame_time = if self.average_frame_time == 0.0 {
+            micros as f64
+        } else {
+            self.average_frame_time * 0.9 + micros as f64 * 0.1
+        };
+    }
+}
+
+/// Collects elapsed time and hands it out as whole steps.
+pub struct Accumulator {
+    pub timing: Timing,
+    time: u64
+}
+
+impl Accumulator {
+    /// Starts with one step due, so the first frame has something to draw.
+    pub fn new(timing: Timing) -> Accumulator {
+        Accumulator {
+            timing: timing,
+            time: timing.step()
+        }
+    }
+
+    pub fn add(&mut self, micros: u64) -> () {
+        self.time += micros;
+    }
+
+    /// Take the steps due now, at most `max_updates`, dropping any beyond
+    /// that. Returns (steps to run, steps dropped).
+    pub fn take_steps(&mut self) -> (u32, u64) {
+        let step = self.timing.step();
+        if step == 0 {
+            return (1, 0);
+        }
+        let due = self.time / step;
+        self.time %= step;
+        let run = ::std::cmp::min(due, self.timing.max_updates as u64);
+        (run as u32, due - run)
+    }
+
+    /// Time left until the next step is due.
+    pub fn until_next(&self) -> u64 {
+        self.timing.step().saturating_sub(self.time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Timing, Accumulator};
+
+    #[test]
+    fn gb_step() {
+        assert_eq!(Timing::new().step(), 16742);
+    }
+
+    #[test]
+    fn steps_and_limit() {
+        let timing = Timing { rate: 100.0, max_updates: 3 };
+        let mut acc = Accumulator::new(timing);
+        assert_eq!(acc.take_steps(), (1, 0));
+        acc.add(5_000);
+        assert_eq!(acc.take_steps(), (0, 0));
+        assert_eq!(acc.until_next(), 5_000);
+        acc.add(7_000);
+        assert_eq!(acc.take_steps(), (1, 0));
+        assert_eq!(acc.until_next(), 8_000);
+        acc.add(100_000);
+        assert_eq!(acc.take_steps(), (3, 7));
+    }
+}
@@ -1,43 +1,76 @@
+use std::any::{Any, TypeId};
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::marker::PhantomData;
 
 use ::math::Vector;
 use ::input::InputState;
 use ::gfx::screen::Screen;
 use ::gfx::image::ImageDelegate;
+use ::game::components::*;
+use ::game::camera::Camera;
+use ::game::dialogue::Dialogue;
 
-pub type EntityID = u32;
+/// An entity handle. The generation is bumped whenever an index is recycled,
+/// so handles to destroyed entities never alias newer ones.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct EntityID {
+    index: u32,
+    generation: u32
+}
+
+impl EntityID {
+    #[inline]
+    pub fn index(&self) -> u32 { self.index }
+    #[inline]
+    pub fn generation(&self) -> u32 { self.generation }
+}
 
 pub type Thinker = Fn(Rc<RefCell<World>>, EntityID, InputState) -> ();
+pub type AnimationCallback = Fn(Rc<RefCell<World>>, EntityID, &str) -> ();
+/// Draws an entity into one of the screen's layers, usually `sprites`.
 pub type Drawer = Fn(Rc<RefCell<World>>, Rc<RefCell<Screen>>, EntityID) -> ();
 
+/// Type-erased storage for one component type, so entities can be cleaned up
+/// without knowing every component type.
+trait AnyStorage {
+    fn remove_entity(&mut self, entity: EntityID) -> ();
+    fn as_any(&self) -> &Any;
+    fn as_any_mut(&mut self) -> &mut Any;
+}
+
+impl<T: 'static> AnyStorage for HashMap<EntityID, T> {
+    fn remove_entity(&mut self, entity: EntityID) -> () {
+        self.remove(&entity);
+    }
+
+    fn as_any(&self) -> &Any { self }
+    fn as_any_mut(&mut self) -> &mut Any { self }
+}
+
 pub struct World {
+    pub camera: Camera,
+    dialogue: Option<Dialogue>,
     entities: HashSet<EntityID>,
-    positions: HashMap<EntityID, Vector>,
-    velocities: HashMap<EntityID, Vector>,
-    thinkers: HashMap<EntityID, Rc<Thinker>>,
-    drawers: HashMap<EntityID, Rc<Drawer>>,
-    sprites: HashMap<EntityID, ImageDelegate>,
+    components: HashMap<TypeId, Box<AnyStorage>>,
 
-    entity_counter: EntityID
+    generations: Vec<u32>,
+    free_indices: Vec<u32>,
+    pending_destroy: Vec<EntityID>
 }
 
 macro_rules! make_component_funcs {
-    ($getter:ident, $setter:ident, $component_type:ty, $field:ident) => (
+    ($getter:ident, $setter:ident, $component_type:ty, $component:ident) => (
         #[inline]
         pub fn $getter(&self, entity: EntityID) -> Option<$component_type> {
-            if let Some(s) = self.$field.get(&entity) {
-                Some(s.clone())
-            } else {
-                None
-            }
+            self.get::<$component>(entity).map(|c| c.0.clone())
         }
 
         #[inline]
         pub fn $setter(&mut self, entity: EntityID, value: $component_type) -> () {
-            self.$field.insert(entity, value);
+            self.insert(entity, $component(value));
         }
     );
 }
@@ -45,13 +78,13 @@ macro_rules! make_component_funcs {
 impl World {
     pub fn new() -> Self {
         World {
+            camera: Camera::new(),
+            dialogue: None,
             entities: HashSet::with_capacity(512),
-            positions: HashMap::new(),
-            velocities: HashMap::new(),
-            thinkers: HashMap::new(),
-            drawers: HashMap::new(),
-            sprites: HashMap::new(),
-            entity_counter: 0
+            components: HashMap::new(),
+            generations: Vec::with_capacity(512),
+            free_indices: Vec::new(),
+            pending_destroy: Vec::new()
         }
     }
 
@@ -63,22 +96,303 @@ impl World {
 
     /// Allocates an entity ID. Does not set any components.
     pub fn create_entity(&mut self) -> EntityID {
-        let mut i = self.entity_counter;
-        while self.entities.contains(&i) {
-            i = i.wrapping_add(1);
-        }
+        let i = match self.free_indices.pop() {
+            Some(index) => EntityID { index: index, generation: self.generations[index as usize] },
+            None => {
+                self.generations.push(0);
+                EntityID { index: (self.generations.len() - 1) as u32, generation: 0 }
+            }
+        };
 
         if !self.entities.insert(i) {
-            error!("Entity ID {} re-inserted? This shouldn't happen.", i);
+            error!("Entity ID {:?} re-inserted? This shouldn't happen.", i);
         }
 
-        self.entity_counter = i.wrapping_add(1);
         i
     }
 
-    make_component_funcs!(position, set_position, Vector, positions);
-    make_component_funcs!(velocity, set_velocity, Vector, velocities);
-    make_component_funcs!(thinker, set_thinker, Rc<Thinker>, thinkers);
-    make_component_funcs!(drawer, set_drawer, Rc<Drawer>, drawers);
-    make_component_funcs!(sprite, set_sprite, ImageDelegate, sprites);
+    /// True if the entity exists and has not been destroyed.
+    #[inline]
+    pub fn is_alive(&self, entity: EntityID) -> bool {
+        self.entities.contains(&entity)
+    }
+
+    /// Removes an entity and all of its components immediately. Returns false
+    /// if the entity was already dead.
+    pub fn destroy_entity(&mut self, entity: EntityID) -> bool {
+        if !self.entities.remove(&entity) {
+            return false;
+        }
+
+        for storage in self.components.values_mut() {
+            storage.remove_entity(entity);
+        }
+
+        let generation = &mut self.generations[entity.index as usize];
+        *generation = generation.wrapping_add(1);
+        self.free_indices.push(entity.index);
+        true
+    }
+
+    /// Marks an entity to be destroyed at the end of the current update. Safe
+    /// to call from a `Thinker` while entities are being iterated.
+    pub fn queue_destroy(&mut self, entity: EntityID) -> () {
+        if self.is_alive(entity) && !self.pending_destroy.contains(&entity) {
+            self.pending_destroy.push(entity);
+        }
+    }
+
+    /// Destroys every entity passed to `queue_destroy` since the last flush.
+    pub fn flush_destroyed(&mut self) -> () {
+        let pending: Vec<EntityID> = self.pending_destroy.drain(..).collect();
+        for e in pending.into_iter() {
+            self.destroy_entity(e);
+        }
+    }
+
+    /// Show a dialogue box. Thinkers don't run while it is open. Replaces any
+    /// dialogue already open.
+    pub fn open_dialogue(&mut self, dialogue: Dialogue) -> () {
+        if self.dialogue.is_some() {
+            warn!("Opening a dialogue over another one");
+        }
+        self.dialogue = Some(dialogue);
+    }
+
+    #[inline]
+    pub fn dialogue_open(&self) -> bool {
+        self.dialogue.is_some()
+    }
+
+    #[inline]
+    pub fn dialogue_mut(&mut self) -> Option<&mut Dialogue> {
+        self.dialogue.as_mut()
+    }
+
+    pub fn take_dialogue(&mut self) -> Option<Dialogue> {
+        self.dialogue.take()
+    }
+
+    /// Attaches a component to an entity, replacing any previous component of
+    /// the same type.
+    pub fn insert<T: 'static>(&mut self, entity: EntityID, value: T) -> () {
+        if !self.is_alive(entity) {
+            warn!("Setting component on dead entity {:?}", entity);
+            return;
+        }
+        self.storage_mut::<T>().insert(entity, value);
+    }
+
+    /// Detaches a component from an entity, returning it.
+    pub fn remove<T: 'static>(&mut self, entity: EntityID) -> Option<T> {
+        match self.components.get_mut(&TypeId::of::<T>()) {
+            Some(s) => s.as_any_mut().downcast_mut::<HashMap<EntityID, T>>().and_then(|s| s.remove(&entity)),
+            None => None
+        }
+    }
+
+    #[inline]
+    pub fn get<T: 'static>(&self, entity: EntityID) -> Option<&T> {
+        self.storage::<T>().and_then(|s| s.get(&entity))
+    }
+
+    #[inline]
+    pub fn get_mut<T: 'static>(&mut self, entity: EntityID) -> Option<&mut T> {
+        match self.components.get_mut(&TypeId::of::<T>()) {
+            Some(s) => s.as_any_mut().downcast_mut::<HashMap<EntityID, T>>().and_then(|s| s.get_mut(&entity)),
+            None => None
+        }
+    }
+
+    #[inline]
+    pub fn has<T: 'static>(&self, entity: EntityID) -> bool {
+        self.get::<T>(entity).is_some()
+    }
+
+    /// Iterate the entities that have every component in `Q`, a tuple of
+    /// component types, along with references to those components.
+    ///
+    /// ```ignore
+    /// for (id, (pos, vel)) in world.query::<(WorldPosition, Velocity)>() { ... }
+    /// ```
+    pub fn query<'a, Q: Query<'a>>(&'a self) -> QueryIter<'a, Q> {
+        QueryIter {
+            world: self,
+            candidates: Q::candidates(self),
+            _query: PhantomData
+        }
+    }
+
+    fn storage<T: 'static>(&self) -> Option<&HashMap<EntityID, T>> {
+        self.components.get(&TypeId::of::<T>()).and_then(|s| s.as_any().downcast_ref())
+    }
+
+    fn storage_mut<T: 'static>(&mut self) -> &mut HashMap<EntityID, T> {
+        let storage = self.components.entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(HashMap::<EntityID, T>::new()) as Box<AnyStorage>);
+        match storage.as_any_mut().downcast_mut() {
+            Some(s) => s,
+            None => panic!("Component storage has the wrong type. This shouldn't happen.")
+        }
+    }
+
+    make_component_funcs!(position, set_position, Vector, WorldPosition);
+    make_component_funcs!(velocity, set_velocity, Vector, Velocity);
+    make_component_funcs!(thinker, set_thinker, Rc<Thinker>, ThinkerRef);
+    make_component_funcs!(drawer, set_drawer, Rc<Drawer>, DrawerRef);
+    make_component_funcs!(sprite, set_sprite, ImageDelegate, SpriteImage);
+}
+
+/// A tuple of component types that can be fetched together for one entity.
+pub trait Query<'a> {
+    type Item;
+
+    /// Entities that may match, taken from the first component's storage.
+    fn candidates(world: &'a World) -> Box<Iterator<Item=EntityID> + 'a>;
+
+    fn fetch(world: &'a World, entity: EntityID) -> Option<Self::Item>;
+}
+
+pub struct QueryIter<'a, Q: Query<'a>> {
+    world: &'a World,
+    candidates: Box<Iterator<Item=EntityID> + 'a>,
+    _query: PhantomData<Q>
+}
+
+impl<'a, Q: Query<'a>> Iterator for QueryIter<'a, Q> {
+    type Item = (EntityID, Q::Item);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for e in self.candidates.by_ref() {
+            if let Some(item) = Q::fetch(self.world, e) {
+                return Some((e, item));
+            }
+        }
+        None
+    }
+}
+
+macro_rules! impl_query {
+    ($first:ident $first_v:ident $(, $t:ident $v:ident)*) => (
+        impl<'a, $first: 'static $(, $t: 'static)*> Query<'a> for ($first, $($t,)*) {
+            type Item = (&'a $first, $(&'a $t,)*);
+
+            fn candidates(world: &'a World) -> Box<Iterator<Item=EntityID> + 'a> {
+                match world.storage::<$first>() {
+                    Some(s) => Box::new(s.keys().cloned()),
+                    None => Box::new(None.into_iter())
+                }
+            }
+
+            #[inline]
+            fn fetch(world: &'a World, entity: EntityID) -> Option<Self::Item> {
+                match (world.get::<$first>(entity), $(world.get::<$t>(entity),)*) {
+                    (Some($first_v), $(Some($v),)*) => Some(($first_v, $($v,)*)),
+                    _ => None
+                }
+            }
+        }
+    );
+}
+
+impl_query!(A a);
+impl_query!(A a, B b);
+impl_query!(A a, B b, C c);
+impl_query!(A a, B b, C c, D d);
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use std::cell::RefCell;
+
+    use super::{World, EntityID};
+    use ::game::components::{WorldPosition, Velocity, ThinkerRef, OnAnimationFinish};
+    use ::game::scene::update_world;
+    use ::gfx::animation::{Animation, Clip, Frame, PlayMode};
+    use ::gfx::image::SubImage;
+    use ::testing::solid;
+    use ::input::InputState;
+    use ::math::Vector;
+    use ::math::rect::Rect;
+
+    #[test]
+    fn destroy_removes_components() {
+        let mut world = World::new();
+        let e = world.create_entity();
+        world.insert(e, WorldPosition(Vector::new(1.0, 2.0)));
+        world.insert(e, Velocity(Vector::new(0.0, 0.0)));
+        world.insert(e, 7u32);
+
+        assert!(world.destroy_entity(e));
+        assert!(!world.is_alive(e));
+        assert!(!world.has::<WorldPosition>(e));
+        assert!(!world.has::<Velocity>(e));
+        assert!(!world.has::<u32>(e));
+        assert_eq!(world.query::<(WorldPosition,)>().count(), 0);
+        assert!(!world.destroy_entity(e));
+    }
+
+    #[test]
+    fn recycled_index_gets_new_generation() {
+        let mut world = World::new();
+        let old = world.create_entity();
+        world.destroy_entity(old);
+        let new = world.create_entity();
+
+        assert_eq!(new.index(), old.index());
+        assert!(new.generation() != old.generation());
+        assert!(world.is_alive(new));
+        assert!(!world.is_alive(old));
+
+        world.insert(old, 1u32);
+        assert!(!world.has::<u32>(old));
+        assert!(!world.has::<u32>(new));
+        assert!(!world.destroy_entity(old));
+        assert!(world.is_alive(new));
+    }
+
+    #[test]
+    fn thinker_can_queue_destroy() {
+        let w = Rc::new(RefCell::new(World::new()));
+        let (a, b) = {
+            let mut world = w.borrow_mut();
+            (world.create_entity(), world.create_entity())
+        };
+        // a destroys itself and b; b must not think once it's gone.
+        let thought = Rc::new(RefCell::new(Vec::new()));
+        for &e in [a, b].iter() {
+            let thought = thought.clone();
+            w.borrow_mut().insert(e, ThinkerRef(Rc::new(move |w: Rc<RefCell<World>>, i: EntityID, _: InputState| {
+                thought.borrow_mut().push(i);
+                let mut world = w.borrow_mut();
+                world.queue_destroy(a);
+                world.queue_destroy(b);
+            })));
+        }
+
+        update_world(&w, InputState::new());
+        assert!(!w.borrow().is_alive(a));
+        assert!(!w.borrow().is_alive(b));
+        assert!(w.borrow().query::<(ThinkerRef,)>().next().is_none());
+        // Queued entities stay alive until the flush, so both thought once.
+        assert_eq!(thought.borrow().len(), 2);
+
+        update_world(&w, InputState::new());
+        assert_eq!(thought.borrow().len(), 2);
+    }
+
+    #[test]
+    fn animation_callback_destroy_is_flushed() {
+        let w = Rc::new(RefCell::new(World::new()));
+        let e = w.borrow_mut().create_entity();
+        let image = SubImage::new(solid(8, 8, 0), Rect::new(0, 0, 8, 8));
+        let clip = Clip::new(vec![Frame::new(image, 1)], PlayMode::Once);
+        w.borrow_mut().insert(e, Animation::new().with_clip("die", clip));
+        w.borrow_mut().insert(e, OnAnimationFinish(Rc::new(|w: Rc<RefCell<World>>, i: EntityID, _: &str| {
+            w.borrow_mut().queue_destroy(i);
+        })));
+
+        update_world(&w, InputState::new());
+        assert!(!w.borrow().is_alive(e));
+    }
 }
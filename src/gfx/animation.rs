@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+
+use ::gfx::image::SubImage;
+use ::math::Position;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PlayMode {
+    /// Start over after the last frame.
+    Loop,
+    /// Run forward then backward.
+    PingPong,
+    /// Stop on the last frame.
+    Once
+}
+
+#[derive(Clone)]
+pub struct Frame {
+    pub image: SubImage,
+    /// How many game frames to show this frame for.
+    pub duration: u32,
+    /// Where the image's top left sits in the whole sprite, for frames
+    /// trimmed of transparent edges.
+    pub offset: Position
+}
+
+impl Frame {
+    pub fn new(image: SubImage, duration: u32) -> Frame {
+        Frame {
+            image: image,
+            duration: duration,
+            offset: Position::new(0, 0)
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Clip {
+    pub frames: Vec<Frame>,
+    pub mode: PlayMode
+}
+
+impl Clip {
+    pub fn new(frames: Vec<Frame>, mode: PlayMode) -> Clip {
+        Clip {
+            frames: frames,
+            mode: mode
+        }
+    }
+
+    /// A clip showing every image for the same number of frames.
+    pub fn uniform(images: Vec<SubImage>, duration: u32, mode: PlayMode) -> Clip {
+        Clip::new(images.into_iter().map(|i| Frame::new(i, duration)).collect(), mode)
+    }
+}
+
+/// Named clips and the playback state of the one playing.
+#[derive(Clone)]
+pub struct Animation {
+    clips: HashMap<String, Clip>,
+    current: String,
+    frame: usize,
+    timer: u32,
+    backwards: bool,
+    finished: bool
+}
+
+impl Animation {
+    pub fn new() -> Animation {
+        Animation {
+            clips: HashMap::new(),
+            current: String::new(),
+            frame: 0,
+            timer: 0,
+            backwards: false,
+            finished: false
+        }
+    }
+
+    /// Add a clip. The first clip added starts playing.
+    pub fn add_clip(&mut self, name: &str, clip: Clip) -> () {
+        self.clips.insert(name.to_string(), clip);
+        if self.current.is_empty() {
+            self.current = name.to_string();
+        }
+    }
+
+    pub fn with_clip(mut self, name: &str, clip: Clip) -> Animation {
+        self.add_clip(name, clip);
+        self
+    }
+
+    /// Switch to a clip from its first frame. Does nothing if that clip is
+    /// already playing; use `restart` to start it over.
+    pub fn play(&mut self, name: &str) -> () {
+        if self.current == name && !self.finished {
+            return;
+        }
+        if !self.clips.contains_key(name) {
+            warn!("No animation clip named \"{}\"", name);
+            return;
+        }
+        self.current = name.to_string();
+        self.restart();
+    }
+
+    pub fn restart(&mut self) -> () {
+        self.frame = 0;
+        self.timer = 0;
+        self.backwards = false;
+        self.finished = false;
+    }
+
+    #[inline]
+    pub fn current_clip(&self) -> &str {
+        &self.current
+    }
+
+    /// True once a `Once` clip has shown its last frame.
+    #[inline]
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// The frame showing, with its duration and offset.
+    pub fn current(&self) -> Option<&Frame> {
+        self.clips.get(&self.current).and_then(|c| c.frames.get(self.frame))
+    }
+
+    pub fn current_frame(&self) -> Option<&SubImage> {
+        self.current().map(|f| &f.image)
+    }
+
+    /// Step one game frame. Returns true when the clip completes: a `Once`
+    /// clip reaching its end, or a `Loop` or `PingPong` clip finishing a
+    /// cycle.
+    pub fn advance(&mut self) -> bool {
+        if self.finished {
+            return false;
+        }
+        let clip = match self.clips.get(&self.current) {
+            Some(c) if !c.frames.is_empty() => c,
+            _ => return false
+        };
+
+        self.timer += 1;
+        if self.timer < ::std::cmp::max(clip.frames[self.frame].duration, 1) {
+            return false;
+        }
+        self.timer = 0;
+
+        let len = clip.frames.len();
+        match clip.mode {
+            PlayMode::Once => {
+                if self.frame + 1 < len {
+                    self.frame += 1;
+                    false
+                } else {
+                    self.finished = true;
+                    true
+                }
+            },
+            PlayMode::Loop => {
+                self.frame = (self.frame + 1) % len;
+                self.frame == 0
+            },
+            PlayMode::PingPong => {
+                if len == 1 {
+                    return true;
+                }
+                if !self.backwards {
+                    if self.frame + 1 < len {
+                        self.frame += 1;
+                        return false;
+                    }
+                    self.backwards = true;
+                }
+                self.frame -= 1;
+                if self.frame == 0 {
+                    self.backwards = false;
+                    true
+                } else {
+                    false
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Animation, Clip, Frame, PlayMode};
+    use ::gfx::image::SubImage;
+    use ::math::rect::Rect;
+    use ::testing::solid;
+
+    /// A clip whose frame n is the pixel at x = n, shown for `durations[n]`.
+    fn clip(durations: &[u32], mode: PlayMode) -> Clip {
+        let sheet = solid(8, 1, 0);
+        Clip::new(durations.iter().enumerate().map(|(i, &d)| {
+            Frame::new(SubImage::new(sheet.clone(), Rect::new(i as i32, 0, 1, 1)), d)
+        }).collect(), mode)
+    }
+
+    /// Advance `n` times, returning (frame shown, completed) after each.
+    fn run(animation: &mut Animation, n: usize) -> Vec<(i32, bool)> {
+        (0..n).map(|_| {
+            let done = animation.advance();
+            (animation.current_frame().unwrap().rect().x(), done)
+        }).collect()
+    }
+
+    #[test]
+    fn loop_wraps_and_reports_each_cycle() {
+        let mut a = Animation::new().with_clip("walk", clip(&[1, 1, 1], PlayMode::Loop));
+        assert_eq!(run(&mut a, 6), vec![(1, false), (2, false), (0, true), (1, false), (2, false), (0, true)]);
+        assert!(!a.is_finished());
+    }
+
+    #[test]
+    fn ping_pong_turns_at_the_ends() {
+        let mut a = Animation::new().with_clip("bob", clip(&[1, 1, 1], PlayMode::PingPong));
+        assert_eq!(run(&mut a, 6), vec![(1, false), (2, false), (1, false), (0, true), (1, false), (2, false)]);
+    }
+
+    #[test]
+    fn single_frame_ping_pong_completes_every_frame() {
+        let mut a = Animation::new().with_clip("idle", clip(&[2], PlayMode::PingPong));
+        assert_eq!(run(&mut a, 4), vec![(0, false), (0, true), (0, false), (0, true)]);
+    }
+
+    #[test]
+    fn once_stops_on_the_last_frame() {
+        let mut a = Animation::new().with_clip("die", clip(&[1, 1, 1], PlayMode::Once));
+        assert_eq!(run(&mut a, 4), vec![(1, false), (2, false), (2, true), (2, false)]);
+        assert!(a.is_finished());
+
+        // Playing a finished clip again starts it over.
+        a.play("die");
+        assert!(!a.is_finished());
+        assert_eq!(a.current_frame().unwrap().rect().x(), 0);
+    }
+
+    #[test]
+    fn per_frame_durations() {
+        let mut a = Animation::new().with_clip("blink", clip(&[2, 1, 3], PlayMode::Loop));
+        let frames: Vec<i32> = run(&mut a, 7).into_iter().map(|(f, _)| f).collect();
+        assert_eq!(frames, vec![0, 1, 2, 2, 2, 0, 0]);
+
+        // A zero duration still shows the frame for one game frame.
+        let mut a = Animation::new().with_clip("zero", clip(&[0, 0], PlayMode::Loop));
+        assert_eq!(run(&mut a, 2), vec![(1, false), (0, true)]);
+    }
+
+    #[test]
+    fn play_switches_clips_from_the_start() {
+        let mut a = Animation::new()
+            .with_clip("a", clip(&[1, 1], PlayMode::Loop))
+            .with_clip("b", clip(&[1, 1, 1], PlayMode::Loop));
+        assert_eq!(a.current_clip(), "a");
+        a.advance();
+        a.play("a");
+        assert_eq!(a.current_frame().unwrap().rect().x(), 1);
+        a.play("b");
+        assert_eq!((a.current_clip(), a.current_frame().unwrap().rect().x()), ("b", 0));
+        a.play("missing");
+        assert_eq!(a.current_clip(), "b");
+    }
+}
@@ -1,6 +1,137 @@
 use ::math::rect::Rect;
-use ::gfx::image::Image;
+use ::gfx::image::{Image, TRANSPARENT};
+
+/// Clockwise rotation applied after flipping.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Rotation {
+    None,
+    Cw90,
+    Cw180,
+    Cw270
+}
+
+/// Per-blit transforms. Flips are applied to the source first, then the
+/// rotation. Source shades are looked up in `palette`, like the GB's
+/// BGP/OBP0/OBP1 registers; the `transparent` index and anything above 3 are
+/// not drawn.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BlitOptions {
+    pub flip_x: bool,
+    pub flip_y: bool,
+    pub rotation: Rotation,
+    pub palette: [u8; 4],
+    pub transparent: Option<u8>
+}
+
+impl BlitOptions {
+    pub fn new() -> BlitOptions {
+        BlitOptions {
+            flip_x: false,
+            flip_y: false,
+            rotation: Rotation::None,
+            palette: [0, 1, 2, 3],
+            transparent: Some(TRANSPARENT)
+        }
+    }
+
+    pub fn flip_x(mut self, flip: bool) -> BlitOptions {
+        self.flip_x = flip;
+        self
+    }
+
+    pub fn flip_y(mut self, flip: bool) -> BlitOptions {
+        self.flip_y = flip;
+        self
+    }
+
+    pub fn rotation(mut self, rotation: Rotation) -> BlitOptions {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn palette(mut self, palette: [u8; 4]) -> BlitOptions {
+        self.palette = palette;
+        self
+    }
+
+    /// Use a GB palette register value. The GB numbers colors and shades
+    /// lightest first, so both are reversed into this engine's darkest first
+    /// indices.
+    pub fn gb_palette(mut self, register: u8) -> BlitOptions {
+        for i in 0..4 {
+            let gb_color = 3 - i;
+            let gb_shade = (register >> (gb_color * 2)) & 3;
+            self.palette[i] = 3 - gb_shade;
+        }
+        self
+    }
+
+    pub fn transparent(mut self, transparent: Option<u8>) -> BlitOptions {
+        self.transparent = transparent;
+        self
+    }
+
+    /// The shade to draw for a source pixel, or None if it is transparent.
+    #[inline]
+    pub fn map(&self, color: u8) -> Option<u8> {
+        if color > 3 || Some(color) == self.transparent {
+            None
+        } else {
+            Some(self.palette[color as usize])
+        }
+    }
+
+    /// True if the blit moves pixels around rather than copying them in
+    /// place.
+    #[inline]
+    pub fn is_transformed(&self) -> bool {
+        self.flip_x || self.flip_y || self.rotation != Rotation::None
+    }
+
+    /// Size of a `w` by `h` source once transformed.
+    #[inline]
+    pub fn output_size(&self, w: u32, h: u32) -> (u32, u32) {
+        match self.rotation {
+            Rotation::Cw90 | Rotation::Cw270 => (h, w),
+            _ => (w, h)
+        }
+    }
+
+    /// Maps an output pixel back to the pixel of a `w` by `h` source it comes
+    /// from. Both are relative to their top left.
+    #[inline]
+    pub fn source_offset(&self, ox: i32, oy: i32, w: i32, h: i32) -> (i32, i32) {
+        let (fx, fy) = match self.rotation {
+            Rotation::None => (ox, oy),
+            Rotation::Cw90 => (oy, h - 1 - ox),
+            Rotation::Cw180 => (w - 1 - ox, h - 1 - oy),
+            Rotation::Cw270 => (w - 1 - oy, ox)
+        };
+        (
+            if self.flip_x { w - 1 - fx } else { fx },
+            if self.flip_y { h - 1 - fy } else { fy }
+        )
+    }
+
+    /// Maps the margins trimmed from a source (left, top, right, bottom) to
+    /// the left and top margins they become in the output.
+    pub fn output_margins(&self, left: i32, top: i32, right: i32, bottom: i32) -> (i32, i32) {
+        let (l, r) = if self.flip_x { (right, left) } else { (left, right) };
+        let (t, b) = if self.flip_y { (bottom, top) } else { (top, bottom) };
+        match self.rotation {
+            Rotation::None => (l, t),
+            Rotation::Cw90 => (b, l),
+            Rotation::Cw180 => (r, b),
+            Rotation::Cw270 => (t, r)
+        }
+    }
+}
 
 pub trait Blit {
-    fn blit_to(&self, src: Option<Rect>, target: &mut Image, dst: Option<Rect>) -> ();
+    fn blit_with(&self, src: Option<Rect>, target: &mut Image, dst: Option<Rect>, options: &BlitOptions) -> ();
+
+    #[inline]
+    fn blit_to(&self, src: Option<Rect>, target: &mut Image, dst: Option<Rect>) -> () {
+        self.blit_with(src, target, dst, &BlitOptions::new())
+    }
 }
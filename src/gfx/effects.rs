@@ -0,0 +1,180 @@
+//! Palette effects, done the way GB games do them: by shifting which color
+//! each shade shows (BGP) rather than touching any image.
+
+/// Which end of the palette an effect moves towards.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Tone {
+    Dark,
+    Light
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EffectKind {
+    /// From the normal palette to a single tone, and stay there.
+    FadeOut(Tone),
+    /// From a single tone back to the normal palette.
+    FadeIn(Tone),
+    /// Jump straight to a tone, then fade back.
+    Flash(Tone),
+    /// Fade out and back in `count` times, or forever with `None`.
+    Pulse(Tone, Option<u32>)
+}
+
+pub struct PaletteEffect {
+    pub kind: EffectKind,
+    /// Length of the effect, or of one pulse.
+    pub frames: u32,
+    elapsed: u32,
+    on_finish: Option<Box<FnMut()>>
+}
+
+/// Shades each shade shows with no effect running.
+pub const IDENTITY: [u8; 4] = [0, 1, 2, 3];
+
+/// The shade map with every shade moved `steps` towards `tone`.
+pub fn shifted(tone: Tone, steps: u8) -> [u8; 4] {
+    let mut map = IDENTITY;
+    for shade in map.iter_mut() {
+        *shade = match tone {
+            Tone::Dark => shade.saturating_sub(steps),
+            Tone::Light => ::std::cmp::min(*shade + steps, 3)
+        };
+    }
+    map
+}
+
+impl PaletteEffect {
+    pub fn new(kind: EffectKind, frames: u32) -> PaletteEffect {
+        PaletteEffect {
+            kind: kind,
+            frames: frames,
+            elapsed: 0,
+            on_finish: None
+        }
+    }
+
+    pub fn fade_out(tone: Tone, frames: u32) -> PaletteEffect {
+        PaletteEffect::new(EffectKind::FadeOut(tone), frames)
+    }
+
+    pub fn fade_in(tone: Tone, frames: u32) -> PaletteEffect {
+        PaletteEffect::new(EffectKind::FadeIn(tone), frames)
+    }
+
+    pub fn flash(tone: Tone, frames: u32) -> PaletteEffect {
+        PaletteEffect::new(EffectKind::Flash(tone), frames)
+    }
+
+    pub fn pulse(tone: Tone, frames: u32, count: Option<u32>) -> PaletteEffect {
+        PaletteEffect::new(EffectKind::Pulse(tone, count), frames)
+    }
+
+    /// Run `f` once the effect ends. Effects replaced before they end don't
+    /// call it.
+    pub fn on_finish<F: FnMut() + 'static>(mut self, f: F) -> PaletteEffect {
+        self.on_finish = Some(Box::new(f));
+        self
+    }
+
+    fn tone(&self) -> Tone {
+        match self.kind {
+            EffectKind::FadeOut(t) | EffectKind::FadeIn(t) | EffectKind::Flash(t) | EffectKind::Pulse(t, _) => t
+        }
+    }
+
+    fn total_frames(&self) -> Option<u32> {
+        match self.kind {
+            EffectKind::Pulse(_, None) => None,
+            EffectKind::Pulse(_, Some(count)) => Some(self.frames * count),
+            _ => Some(self.frames)
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.total_frames().map_or(false, |total| self.elapsed >= total)
+    }
+
+    /// How far towards the tone the palette is, from 0 to 1.
+    fn amount(&self) -> f32 {
+        if self.frames == 0 {
+            return match self.kind {
+                EffectKind::FadeOut(_) => 1.0,
+                _ => 0.0
+            };
+        }
+        let t = ::std::cmp::min(self.elapsed, self.frames) as f32 / self.frames as f32;
+        match self.kind {
+            EffectKind::FadeOut(_) => t,
+            EffectKind::FadeIn(_) | EffectKind::Flash(_) => 1.0 - t,
+            EffectKind::Pulse(_, _) => {
+                let phase = (self.elapsed % self.frames) as f32 / self.frames as f32;
+                1.0 - (phase * 2.0 - 1.0).abs()
+            }
+        }
+    }
+
+    /// The shade map for the current frame.
+    pub fn shade_map(&self) -> [u8; 4] {
+        shifted(self.tone(), (self.amount() * 3.0).round() as u8)
+    }
+
+    /// Move on one frame. Returns the finish callback on the frame the effect
+    /// ends.
+    pub fn advance(&mut self) -> Option<Box<FnMut()>> {
+        if !self.is_finished() {
+            self.elapsed += 1;
+        }
+        if self.is_finished() {
+            self.on_finish.take()
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use std::cell::Cell;
+    use super::{PaletteEffect, Tone, IDENTITY};
+
+    #[test]
+    fn fade_out_ends_dark_and_calls_back_once() {
+        let calls = Rc::new(Cell::new(0));
+        let c = calls.clone();
+        let mut fade = PaletteEffect::fade_out(Tone::Dark, 3).on_finish(move || c.set(c.get() + 1));
+        assert_eq!(fade.shade_map(), IDENTITY);
+        for _ in 0..3 {
+            if let Some(mut f) = fade.advance() {
+                f();
+            }
+        }
+        assert!(fade.is_finished());
+        assert_eq!(fade.shade_map(), [0, 0, 0, 0]);
+        assert!(fade.advance().is_none());
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn flash_starts_full_and_returns() {
+        let mut flash = PaletteEffect::flash(Tone::Light, 6);
+        assert_eq!(flash.shade_map(), [3, 3, 3, 3]);
+        for _ in 0..6 {
+            flash.advance();
+        }
+        assert_eq!(flash.shade_map(), IDENTITY);
+    }
+
+    #[test]
+    fn endless_pulse_never_finishes() {
+        let mut pulse = PaletteEffect::pulse(Tone::Dark, 4, None);
+        for _ in 0..2 {
+            pulse.advance();
+        }
+        assert_eq!(pulse.shade_map(), [0, 0, 0, 0]);
+        for _ in 0..100 {
+            assert!(pulse.advance().is_none());
+        }
+        assert!(!pulse.is_finished());
+    }
+}
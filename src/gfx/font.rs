@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use ::gfx::image::{Image, SubImage, TRANSPARENT};
+use ::gfx::blit::{Blit, BlitOptions};
+
+use ::math::rect::Rect;
+use ::math::size::Size;
+
+pub struct Glyph {
+    pub image: SubImage,
+    /// Pixels to move right after drawing the glyph.
+    pub advance: u32
+}
+
+/// 3x5 glyphs for ' ' to 'Z', one bit per pixel, row first from bit 14.
+const BUILTIN_GLYPHS: [u16; 59] = [
+    0x0000, 0x2482, 0x5A00, 0x5F7D, 0x3C9E, 0x42A1, 0x2AAB, 0x2400,
+    0x1491, 0x4494, 0x0AA8, 0x05D0, 0x0014, 0x01C0, 0x0002, 0x12A4,
+    0x7B6F, 0x2C97, 0x62A7, 0x628E, 0x5BC9, 0x798E, 0x39EF, 0x7292,
+    0x7BEF, 0x7BCE, 0x0410, 0x0414, 0x1511, 0x0E38, 0x4454, 0x6282,
+    0x2BE3, 0x2BED, 0x6BAE, 0x3923, 0x6B6E, 0x79A7, 0x79A4, 0x396B,
+    0x5BED, 0x7497, 0x126A, 0x5BAD, 0x4927, 0x5FED, 0x6B6D, 0x2B6A,
+    0x6BA4, 0x2B73, 0x6BAD, 0x388E, 0x7492, 0x5B6F, 0x5B6A, 0x5BFD,
+    0x5AAD, 0x5A92, 0x72A7
+];
+
+/// A bitmap font cut from a glyph sheet.
+pub struct Font {
+    glyphs: HashMap<char, Glyph>,
+    pub line_height: u32,
+    /// Drawn in place of characters the font doesn't have.
+    pub fallback: Option<char>
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Center,
+    Right
+}
+
+impl Font {
+    /// A fixed-width font from a sheet of `glyph` sized cells holding
+    /// consecutive characters from `first`, left to right then top to bottom.
+    pub fn fixed(sheet: Rc<Image>, glyph: Size, first: char) -> Font {
+        let mut glyphs = HashMap::new();
+        for (i, image) in SubImage::grid(sheet, glyph).into_iter().enumerate() {
+            if let Some(c) = ::std::char::from_u32(first as u32 + i as u32) {
+                glyphs.insert(c, Glyph { image: image, advance: glyph.width });
+            }
+        }
+
+        Font {
+            glyphs: glyphs,
+            line_height: glyph.height,
+            fallback: Some('?')
+        }
+    }
+
+    /// A small font that needs no image file, in 4x6 cells drawn with
+    /// shade 0. Lower case letters use the upper case glyphs.
+    pub fn builtin() -> Font {
+        let width = BUILTIN_GLYPHS.len() * 4;
+        let mut sheet = Image::new((width as u32, 6), TRANSPARENT);
+        for (i, bits) in BUILTIN_GLYPHS.iter().enumerate() {
+            for p in 0..15 {
+                if bits & (1 << (14 - p)) != 0 {
+                    sheet.buffer[(p / 3) * width + i * 4 + p % 3] = 0;
+                }
+            }
+        }
+
+        let mut font = Font::fixed(Rc::new(sheet), Size::new(4, 6), ' ');
+        for c in b'a'..b'z' + 1 {
+            let upper = font.glyphs[&((c - 32) as char)].image.clone();
+            font.glyphs.insert(c as char, Glyph { image: upper, advance: 4 });
+        }
+        font
+    }
+
+    /// A variable-width font from a sheet and a metrics file. Each line of
+    /// the metrics is `<char> <x> <y> <w> <h> [advance]`, or `height <n>` for
+    /// the line height. `space` names the space character and `U+XXXX` any
+    /// other; `#` starts a comment.
+    pub fn from_metrics(sheet: Rc<Image>, metrics: &str) -> Result<Font, String> {
+        let mut font = Font {
+            glyphs: HashMap::new(),
+            line_height: 0,
+            fallback: Some('?')
+        };
+
+        for (n, line) in metrics.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let parse = |s: &str| s.parse::<u32>().map_err(|e| format!("Line {}: {}", n + 1, e));
+
+            if fields[0] == "height" && fields.len() == 2 {
+                font.line_height = try!(parse(fields[1]));
+                continue;
+            }
+            if fields.len() < 5 || fields.len() > 6 {
+                return Err(format!("Line {}: expected <char> <x> <y> <w> <h> [advance]", n + 1));
+            }
+
+            let c = try!(parse_char(fields[0]).ok_or(format!("Line {}: invalid character \"{}\"", n + 1, fields[0])));
+            let (x, y, w, h) = (try!(parse(fields[1])), try!(parse(fields[2])), try!(parse(fields[3])), try!(parse(fields[4])));
+            let advance = if fields.len() == 6 { try!(parse(fields[5])) } else { w + 1 };
+
+            font.line_height = ::std::cmp::max(font.line_height, h);
+            font.glyphs.insert(c, Glyph {
+                image: SubImage::new(sheet.clone(), Rect::new(x as i32, y as i32, w, h)),
+                advance: advance
+            });
+        }
+
+        Ok(font)
+    }
+
+    pub fn glyph(&self, c: char) -> Option<&Glyph> {
+        match self.glyphs.get(&c) {
+            Some(g) => Some(g),
+            None => self.fallback.and_then(|f| self.glyphs.get(&f))
+        }
+    }
+
+    #[inline]
+    fn advance(&self, c: char) -> u32 {
+        self.glyph(c).map_or(0, |g| g.advance)
+    }
+
+    /// Width of a single line of text in pixels.
+    pub fn measure(&self, text: &str) -> u32 {
+        text.chars().map(|c| self.advance(c)).sum()
+    }
+
+    /// Break text into lines no wider than `width`, at spaces where possible.
+    /// Newlines always start a new line.
+    pub fn wrap(&self, text: &str, width: u32) -> Vec<String> {
+        let mut lines = Vec::new();
+        for paragraph in text.split('\n') {
+            let mut line = String::new();
+            let mut line_width = 0;
+            for word in paragraph.split(' ') {
+                let word_width = self.measure(word);
+                let space = if line.is_empty() { 0 } else { self.advance(' ') };
+
+                if line_width + space + word_width <= width {
+                    if !line.is_empty() {
+                        line.push(' ');
+                    }
+                    line.push_str(word);
+                    line_width += space + word_width;
+                    continue;
+                }
+
+                if !line.is_empty() {
+                    lines.push(line);
+                    line = String::new();
+                    line_width = 0;
+                }
+
+                // Words longer than a line are broken wherever they run out.
+                for c in word.chars() {
+                    let w = self.advance(c);
+                    if line_width + w > width && !line.is_empty() {
+                        lines.push(line);
+                        line = String::new();
+                        line_width = 0;
+                    }
+                    line.push(c);
+                    line_width += w;
+                }
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    /// Draw one line with its top left at (x, y), clipped to `bounds`.
+    pub fn draw_line(&self, target: &mut Image, line: &str, x: i32, y: i32, bounds: Rect, options: &BlitOptions) -> () {
+        let mut x = x;
+        for c in line.chars() {
+            if let Some(g) = self.glyph(c) {
+                let size = g.image.size();
+                let clip = Rect::new(x, y, size.width, size.height).clip(&bounds);
+                if !clip.is_zero() {
+                    let src = Rect::new(clip.x() - x, clip.y() - y, clip.w(), clip.h());
+                    g.image.blit_with(Some(src), target, Some(Rect::new(clip.x(), clip.y(), 0, 0)), options);
+                }
+                x += g.advance as i32;
+            }
+        }
+    }
+
+    /// Lay out lines inside `bounds` with the given alignment and draw them.
+    pub fn draw_lines(&self, target: &mut Image, lines: &[String], bounds: Rect, align: Align, options: &BlitOptions) -> () {
+        let mut y = bounds.y();
+        for line in lines.iter() {
+            if y >= bounds.max_y() {
+                break;
+            }
+            let w = self.measure(line) as i32;
+            let x = match align {
+                Align::Left => bounds.x(),
+                Align::Center => bounds.x() + (bounds.w() as i32 - w) / 2,
+                Align::Right => bounds.max_x() - w
+            };
+            self.draw_line(target, line, x, y, bounds, options);
+            y += self.line_height as i32;
+        }
+    }
+}
+
+/// Draw text wrapped to fit inside `bounds`. Shades are remapped through the
+/// options' palette.
+pub fn draw_text(target: &mut Image, font: &Font, text: &str, bounds: Rect, align: Align, options: &BlitOptions) -> () {
+    let lines = font.wrap(text, bounds.w());
+    font.draw_lines(target, &lines, bounds, align, options);
+}
+
+fn parse_char(s: &str) -> Option<char> {
+    if s == "space" {
+        return Some(' ');
+    }
+    if s.starts_with("U+") {
+        return u32::from_str_radix(&s[2..], 16).ok().and_then(::std::char::from_u32);
+    }
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Font;
+    use ::gfx::image::Image;
+    use ::gfx::blit::BlitOptions;
+    use ::math::rect::Rect;
+    use ::testing::solid;
+
+    const METRICS: &'static str = "height 9\n# comment\nspace 0 0 1 8 2\n  a 0 0 3 8\nU+0062 4 0 3 8 5\n";
+
+    /// Space advances 2, `a` 4 and `b` 5.
+    fn font() -> Font {
+        Font::from_metrics(solid(16, 8, 0), METRICS).unwrap()
+    }
+
+    #[test]
+    fn metrics() {
+        let font = font();
+        assert_eq!(font.line_height, 9);
+        assert_eq!(font.glyph(' ').map(|g| g.advance), Some(2));
+        assert_eq!(font.glyph('a').map(|g| g.advance), Some(4));
+        let b = font.glyph('b').unwrap();
+        assert_eq!((b.image.rect(), b.advance), (Rect::new(4, 0, 3, 8), 5));
+        assert!(font.glyph('z').is_none());
+    }
+
+    #[test]
+    fn wrap_at_spaces() {
+        let font = font();
+        assert_eq!(font.wrap("aa bb", 20), vec!["aa bb"]);
+        assert_eq!(font.wrap("aa bb", 19), vec!["aa", "bb"]);
+        assert_eq!(font.wrap("", 10), vec![""]);
+    }
+
+    #[test]
+    fn wrap_keeps_newlines() {
+        assert_eq!(font().wrap("a\n\nb a", 100), vec!["a", "", "b a"]);
+    }
+
+    #[test]
+    fn wrap_breaks_long_words() {
+        let font = font();
+        assert_eq!(font.wrap("aaaaa", 8), vec!["aa", "aa", "a"]);
+        assert_eq!(font.wrap("b aaaaa", 8), vec!["b", "aa", "aa", "a"]);
+        // A glyph wider than the line still gets a line of its own.
+        assert_eq!(font.wrap("bb", 3), vec!["b", "b"]);
+    }
+
+    #[test]
+    fn builtin_font() {
+        let font = Font::builtin();
+        assert_eq!((font.line_height, font.measure("Esc a")), (6, 20));
+
+        let mut target = Image::new((4, 6), 3u8);
+        font.draw_line(&mut target, "t", 0, 0, Rect::new(0, 0, 4, 6), &BlitOptions::new());
+        assert_eq!(target.buffer, vec![0, 0, 0, 3,
+                                       3, 0, 3, 3,
+                                       3, 0, 3, 3,
+                                       3, 0, 3, 3,
+                                       3, 0, 3, 3,
+                                       3, 3, 3, 3]);
+    }
+}
@@ -5,7 +5,10 @@ use ::math::size::Size;
 use ::math::rect::Rect;
 use ::math::Position;
 
-use ::gfx::blit::Blit;
+use ::gfx::blit::{Blit, BlitOptions};
+
+/// Shade index used for pixels that are not drawn.
+pub const TRANSPARENT: u8 = 4;
 
 pub enum ImageDelegate {
     ImageBuf(Rc<Image>),
@@ -32,6 +35,65 @@ impl Clone for SubImage {
     }
 }
 
+impl SubImage {
+    /// A view of part of an image. The rect is clipped to the image bounds.
+    pub fn new(image: Rc<Image>, rect: Rect) -> SubImage {
+        let bounds = Rect::new(0, 0, image.size.width, image.size.height);
+        SubImage {
+            rect: rect.clip(&bounds),
+            image: image
+        }
+    }
+
+    /// Slice a sheet into `cell` sized pieces, left to right then top to
+    /// bottom. Partial cells at the right and bottom edges are dropped.
+    pub fn grid(image: Rc<Image>, cell: Size) -> Vec<SubImage> {
+        let mut frames = Vec::new();
+        if cell.width == 0 || cell.height == 0 {
+            return frames;
+        }
+
+        let columns = image.size.width / cell.width;
+        let rows = image.size.height / cell.height;
+        for row in 0..rows {
+            for column in 0..columns {
+                let rect = Rect::new((column * cell.width) as i32, (row * cell.height) as i32, cell.width, cell.height);
+                frames.push(SubImage::new(image.clone(), rect));
+            }
+        }
+        frames
+    }
+
+    #[inline]
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    #[inline]
+    pub fn size(&self) -> Size {
+        Size::new(self.rect.w(), self.rect.h())
+    }
+}
+
+impl ImageDelegate {
+    #[inline]
+    pub fn size(&self) -> Size {
+        match *self {
+            ImageDelegate::ImageBuf(ref i) => i.size(),
+            ImageDelegate::ImageRef(ref i) => i.size()
+        }
+    }
+
+    /// The backing image and the part of it this shows.
+    #[inline]
+    pub fn source(&self) -> (&Image, Rect) {
+        match *self {
+            ImageDelegate::ImageBuf(ref i) => (i, Rect::new(0, 0, i.size.width, i.size.height)),
+            ImageDelegate::ImageRef(ref i) => (&i.image, i.rect)
+        }
+    }
+}
+
 impl Clone for ImageDelegate {
     fn clone(&self) -> Self {
         use self::ImageDelegate::*;
@@ -52,6 +114,11 @@ impl Image {
         }
     }
 
+    #[inline]
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
     #[inline]
     pub fn set_pixel(&mut self, position: (u32, u32), color: u8) -> Result<(), String> {
         let i = try!(self.index_of_position(position));
@@ -79,58 +146,406 @@ impl Image {
 
     #[inline]
     fn index_of_position(&self, position: (u32, u32)) -> Result<u32, String> {
-        if position.0 > self.size.width || position.1 > self.size.height {
+        if position.0 >= self.size.width || position.1 >= self.size.height {
             return Err("error out of range".to_string());
         }
         Ok(position.1 * self.size.width + position.0)
     }
 }
 
-impl Blit for Image {
-    fn blit_to(&self, src: Option<Rect>, target: &mut Image, dst: Option<Rect>) -> () {
+/// Works out which part of a blit is visible. `src` is clipped to the source
+/// image and the destination is clipped to the target and, when it has a
+/// size, to `dst`. Returns the source rect to copy and where its top left
+/// lands in the target, or None if nothing is visible.
+pub fn clip_blit(src: Rect, src_size: Size, dst: Rect, target_size: Size) -> Option<(Rect, Position)> {
+    let src_clipped = src.clip(&Rect::new(0, 0, src_size.width, src_size.height));
+    if src_clipped.is_zero() {
+        return None;
+    }
+
+    // Trimming the source's top left also moves where it lands.
+    let dst_x = dst.x() + (src_clipped.x() - src.x());
+    let dst_y = dst.y() + (src_clipped.y() - src.y());
+
+    let mut bounds = Rect::new(0, 0, target_size.width, target_size.height);
+    if !dst.is_zero() {
+        bounds = bounds.clip(&dst);
+    }
+    let dst_clipped = Rect::new(dst_x, dst_y, src_clipped.w(), src_clipped.h()).clip(&bounds);
+    if dst_clipped.is_zero() {
+        return None;
+    }
+
+    let src_rect = Rect::new(
+        src_clipped.x() + (dst_clipped.x() - dst_x),
+        src_clipped.y() + (dst_clipped.y() - dst_y),
+        dst_clipped.w(),
+        dst_clipped.h()
+    );
+
+    Some((src_rect, Position::new(dst_clipped.x(), dst_clipped.y())))
+}
+
+impl Image {
+    /// Blit with flips or rotation. Walks the visible part of the output and
+    /// maps each pixel back to the source.
+    fn blit_transformed(&self, src: Rect, target: &mut Image, dst: Rect, options: &BlitOptions) -> () {
+        let (out_w, out_h) = options.output_size(src.w(), src.h());
+
+        let mut bounds = Rect::new(0, 0, target.size.width, target.size.height);
+        if !dst.is_zero() {
+            bounds = bounds.clip(&dst);
+        }
+        let visible = Rect::new(dst.x(), dst.y(), out_w, out_h).clip(&bounds);
+        if visible.is_zero() {
+            return;
+        }
+
+        let target_w = target.size.width as usize;
+        for ty in visible.y()..visible.max_y() {
+            let dst_start = ty as usize * target_w;
+            for tx in visible.x()..visible.max_x() {
+                let (sx, sy) = options.source_offset(tx - dst.x(), ty - dst.y(), src.w() as i32, src.h() as i32);
+                let (sx, sy) = (src.x() + sx, src.y() + sy);
+                if sx < 0 || sy < 0 || sx as u32 >= self.size.width || sy as u32 >= self.size.height {
+                    continue;
+                }
+
+                let color = self.buffer[sy as usize * self.size.width as usize + sx as usize];
+                if let Some(c) = options.map(color) {
+                    target.buffer[dst_start + tx as usize] = c;
+                }
+            }
+        }
+    }
+}
 
+impl Blit for Image {
+    /// Copies `src` (the whole image by default) so its top left lands at the
+    /// position of `dst`, clipping on all sides. A zero-sized `dst` only
+    /// positions the blit; otherwise drawing is also clipped to it.
+    fn blit_with(&self, src: Option<Rect>, target: &mut Image, dst: Option<Rect>, options: &BlitOptions) -> () {
         let src_rect: Rect = match src {
-            Some(s) => s.into(),
+            Some(s) => s,
             None => Rect::new(0, 0, self.size.width, self.size.height)
         };
-
-        let dest_rect: Rect = match dst {
-            Some(s) => s.into(),
-            None => Rect::new(0, 0, target.size.width, target.size.height)
+        let dst_rect: Rect = match dst {
+            Some(d) => d,
+            None => Rect::zero()
         };
 
-        let clip_rect = src_rect.clip(&dest_rect);
-
-        if clip_rect.is_zero() {
-            return;
+        if options.is_transformed() {
+            return self.blit_transformed(src_rect, target, dst_rect, options);
         }
 
-        // TODO optimize
-        for iy in clip_rect.y()..clip_rect.max_y() {
-            for ix in clip_rect.x()..clip_rect.max_x() {
-                let color = match self.get_pixel((ix as u32, iy as u32)) {
-                    Ok(c) => c,
-                    Err(s) => panic!("This shouldn't happen: {}", s)
-                };
-                if color > 3 { continue; }
+        let (src_rect, dst_pos) = match clip_blit(src_rect, self.size, dst_rect, target.size) {
+            Some(r) => r,
+            None => return
+        };
 
-                match target.set_pixel((ix as u32, iy as u32), color) {
-                    Err(s) => panic!("This shouldn't happen: {}", s),
-                    _ => ()
-                };
+        let src_w = self.size.width as usize;
+        let target_w = target.size.width as usize;
+        for row in 0..src_rect.h() as usize {
+            let src_start = (src_rect.y() as usize + row) * src_w + src_rect.x() as usize;
+            let dst_start = (dst_pos.y as usize + row) * target_w + dst_pos.x as usize;
+            for col in 0..src_rect.w() as usize {
+                if let Some(c) = options.map(self.buffer[src_start + col]) {
+                    target.buffer[dst_start + col] = c;
+                }
             }
         }
     }
 }
 
 impl Blit for ImageDelegate {
-    fn blit_to(&self, src: Option<Rect>, target: &mut Image, dst: Option<Rect>) -> () {
+    fn blit_with(&self, src: Option<Rect>, target: &mut Image, dst: Option<Rect>, options: &BlitOptions) -> () {
         match *self {
-            ImageDelegate::ImageBuf(ref i) => i.blit_to(src, target, dst),
-            ImageDelegate::ImageRef(ref i) => {
-                warn!("unimplemented, fall back to image blit");
-                i.image.blit_to(src, target, dst)
+            ImageDelegate::ImageBuf(ref i) => i.blit_with(src, target, dst, options),
+            ImageDelegate::ImageRef(ref i) => i.blit_with(src, target, dst, options)
+        }
+    }
+}
+
+impl Blit for SubImage {
+    /// `src` is relative to the sub image and clipped to it.
+    fn blit_with(&self, src: Option<Rect>, target: &mut Image, dst: Option<Rect>, options: &BlitOptions) -> () {
+        let bounds = Rect::new(0, 0, self.rect.w(), self.rect.h());
+        let requested = match src {
+            Some(s) => s,
+            None => bounds
+        };
+        let clipped = requested.clip(&bounds);
+        if clipped.is_zero() {
+            return;
+        }
+
+        // Trimming the source also moves where it lands.
+        let (dx, dy) = options.output_margins(
+            clipped.x() - requested.x(),
+            clipped.y() - requested.y(),
+            requested.max_x() - clipped.max_x(),
+            requested.max_y() - clipped.max_y()
+        );
+        let dst = match dst {
+            Some(d) if d.is_zero() => Rect::new(d.x() + dx, d.y() + dy, 0, 0),
+            Some(d) => {
+                if d.w() <= dx as u32 || d.h() <= dy as u32 {
+                    return;
+                }
+                Rect::new(d.x() + dx, d.y() + dy, d.w() - dx as u32, d.h() - dy as u32)
+            },
+            None => Rect::new(dx, dy, 0, 0)
+        };
+
+        self.image.blit_with(Some(clipped.offset(self.rect.x(), self.rect.y())), target, Some(dst), options);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::{Image, SubImage, clip_blit};
+    use ::gfx::blit::{Blit, BlitOptions, Rotation};
+    use ::math::rect::Rect;
+    use ::testing::solid;
+    use ::math::size::Size;
+    use ::math::Position;
+
+    /// A 4x4 image where each pixel's shade is (x + y) % 4.
+    fn gradient() -> Image {
+        let mut im = Image::new((4, 4), 0u8);
+        for y in 0..4 {
+            for x in 0..4 {
+                im.set_pixel((x, y), ((x + y) % 4) as u8).unwrap();
             }
         }
+        im
+    }
+
+    fn target() -> Image {
+        Image::new((8, 8), 9u8)
+    }
+
+    #[test]
+    fn clip_blit_origin() {
+        let r = clip_blit(Rect::new(0, 0, 4, 4), Size::new(4, 4), Rect::zero(), Size::new(8, 8));
+        assert_eq!(r, Some((Rect::new(0, 0, 4, 4), Position::new(0, 0))));
+    }
+
+    #[test]
+    fn clip_blit_negative_dst() {
+        let r = clip_blit(Rect::new(0, 0, 4, 4), Size::new(4, 4), Rect::new(-1, -3, 0, 0), Size::new(8, 8));
+        assert_eq!(r, Some((Rect::new(1, 3, 3, 1), Position::new(0, 0))));
+    }
+
+    #[test]
+    fn clip_blit_right_bottom() {
+        let r = clip_blit(Rect::new(0, 0, 4, 4), Size::new(4, 4), Rect::new(6, 7, 0, 0), Size::new(8, 8));
+        assert_eq!(r, Some((Rect::new(0, 0, 2, 1), Position::new(6, 7))));
+    }
+
+    #[test]
+    fn clip_blit_src_outside_image() {
+        let r = clip_blit(Rect::new(-2, 2, 4, 4), Size::new(4, 4), Rect::new(1, 1, 0, 0), Size::new(8, 8));
+        assert_eq!(r, Some((Rect::new(0, 2, 2, 2), Position::new(3, 1))));
+    }
+
+    #[test]
+    fn clip_blit_dst_bounds() {
+        let r = clip_blit(Rect::new(0, 0, 4, 4), Size::new(4, 4), Rect::new(2, 2, 1, 2), Size::new(8, 8));
+        assert_eq!(r, Some((Rect::new(0, 0, 1, 2), Position::new(2, 2))));
+    }
+
+    #[test]
+    fn clip_blit_offscreen() {
+        let s = Size::new(4, 4);
+        let t = Size::new(8, 8);
+        let src = Rect::new(0, 0, 4, 4);
+        assert_eq!(clip_blit(src, s, Rect::new(-4, 0, 0, 0), t), None);
+        assert_eq!(clip_blit(src, s, Rect::new(0, -4, 0, 0), t), None);
+        assert_eq!(clip_blit(src, s, Rect::new(8, 0, 0, 0), t), None);
+        assert_eq!(clip_blit(src, s, Rect::new(0, 8, 0, 0), t), None);
+        assert_eq!(clip_blit(Rect::new(4, 4, 2, 2), s, Rect::zero(), t), None);
+    }
+
+    #[test]
+    fn blit_offset_reads_from_source_origin() {
+        let src = gradient();
+        let mut t = target();
+        src.blit_to(None, &mut t, Some(Rect::new(3, 2, 0, 0)));
+
+        assert_eq!(t.get_pixel((2, 2)).unwrap(), 9);
+        assert_eq!(t.get_pixel((3, 2)).unwrap(), 0);
+        assert_eq!(t.get_pixel((4, 3)).unwrap(), 2);
+        assert_eq!(t.get_pixel((6, 5)).unwrap(), 2);
+        assert_eq!(t.get_pixel((7, 5)).unwrap(), 9);
+    }
+
+    #[test]
+    fn blit_negative_left_top() {
+        let src = gradient();
+        let mut t = target();
+        src.blit_to(None, &mut t, Some(Rect::new(-2, -1, 0, 0)));
+
+        // target (0, 0) is source (2, 1)
+        assert_eq!(t.get_pixel((0, 0)).unwrap(), 3);
+        assert_eq!(t.get_pixel((1, 2)).unwrap(), 2);
+        assert_eq!(t.get_pixel((2, 0)).unwrap(), 9);
+        assert_eq!(t.get_pixel((0, 3)).unwrap(), 9);
+    }
+
+    #[test]
+    fn blit_src_rect() {
+        let src = gradient();
+        let mut t = target();
+        src.blit_to(Some(Rect::new(1, 1, 2, 2)), &mut t, Some(Rect::new(5, 5, 0, 0)));
+
+        assert_eq!(t.get_pixel((5, 5)).unwrap(), 2);
+        assert_eq!(t.get_pixel((6, 6)).unwrap(), 0);
+        assert_eq!(t.get_pixel((7, 7)).unwrap(), 9);
+        assert_eq!(t.get_pixel((4, 4)).unwrap(), 9);
+    }
+
+    #[test]
+    fn blit_skips_transparent() {
+        let mut src = Image::new((2, 2), 4u8);
+        src.set_pixel((1, 1), 1).unwrap();
+        let mut t = target();
+        src.blit_to(None, &mut t, None);
+
+        assert_eq!(t.get_pixel((0, 0)).unwrap(), 9);
+        assert_eq!(t.get_pixel((1, 1)).unwrap(), 1);
+    }
+
+    #[test]
+    fn blit_fully_offscreen() {
+        let src = gradient();
+        let mut t = target();
+        src.blit_to(None, &mut t, Some(Rect::new(-10, 20, 0, 0)));
+
+        assert!(t.buffer.iter().all(|c| *c == 9));
+    }
+
+    #[test]
+    fn blit_flip_x() {
+        let src = gradient();
+        let mut t = target();
+        src.blit_with(Some(Rect::new(0, 0, 4, 1)), &mut t, Some(Rect::new(-1, 0, 0, 0)), &BlitOptions::new().flip_x(true));
+
+        // row 0 reversed is 3 2 1 0, shifted one pixel off the left edge
+        assert_eq!(t.get_pixel((0, 0)).unwrap(), 2);
+        assert_eq!(t.get_pixel((2, 0)).unwrap(), 0);
+        assert_eq!(t.get_pixel((3, 0)).unwrap(), 9);
+    }
+
+    #[test]
+    fn blit_rotate_cw90() {
+        let src = gradient();
+        let mut t = target();
+        src.blit_with(Some(Rect::new(0, 0, 3, 1)), &mut t, None, &BlitOptions::new().rotation(Rotation::Cw90));
+
+        // a 3x1 row becomes a 1x3 column, read top to bottom
+        assert_eq!(t.get_pixel((0, 0)).unwrap(), 0);
+        assert_eq!(t.get_pixel((0, 1)).unwrap(), 1);
+        assert_eq!(t.get_pixel((0, 2)).unwrap(), 2);
+        assert_eq!(t.get_pixel((1, 0)).unwrap(), 9);
+    }
+
+    #[test]
+    fn blit_palette_and_transparent_index() {
+        let src = gradient();
+        let mut t = target();
+        let options = BlitOptions::new().palette([3, 2, 1, 0]).transparent(Some(0));
+        src.blit_with(Some(Rect::new(0, 0, 4, 1)), &mut t, None, &options);
+
+        assert_eq!(t.get_pixel((0, 0)).unwrap(), 9);
+        assert_eq!(t.get_pixel((1, 0)).unwrap(), 2);
+        assert_eq!(t.get_pixel((3, 0)).unwrap(), 0);
+    }
+
+    #[test]
+    fn gb_palette_register() {
+        // 0xE4 is the identity palette on hardware
+        assert_eq!(BlitOptions::new().gb_palette(0xE4).palette, [0, 1, 2, 3]);
+        assert_eq!(BlitOptions::new().gb_palette(0x1B).palette, [3, 2, 1, 0]);
+    }
+
+    /// A 2x2 view at (2, 1) of a sheet filled with shade 3, holding
+    /// 0 1 / 2 0, so anything read from outside it shows up as a 3.
+    fn sub() -> SubImage {
+        let mut sheet = Image::new((6, 4), 3u8);
+        sheet.set_pixel((2, 1), 0).unwrap();
+        sheet.set_pixel((3, 1), 1).unwrap();
+        sheet.set_pixel((2, 2), 2).unwrap();
+        sheet.set_pixel((3, 2), 0).unwrap();
+        SubImage::new(Rc::new(sheet), Rect::new(2, 1, 2, 2))
+    }
+
+    #[test]
+    fn sub_image_reads_only_its_rect() {
+        let mut t = target();
+        sub().blit_to(None, &mut t, Some(Rect::new(1, 1, 0, 0)));
+
+        assert_eq!(t.get_pixel((1, 1)).unwrap(), 0);
+        assert_eq!(t.get_pixel((2, 1)).unwrap(), 1);
+        assert_eq!(t.get_pixel((1, 2)).unwrap(), 2);
+        assert_eq!(t.get_pixel((2, 2)).unwrap(), 0);
+        assert_eq!(t.buffer.iter().filter(|c| **c != 9).count(), 4);
+    }
+
+    #[test]
+    fn sub_image_src_rect_is_clipped_to_it() {
+        let mut t = target();
+        sub().blit_to(Some(Rect::new(1, 0, 2, 2)), &mut t, Some(Rect::new(4, 4, 0, 0)));
+
+        assert_eq!(t.get_pixel((4, 4)).unwrap(), 1);
+        assert_eq!(t.get_pixel((4, 5)).unwrap(), 0);
+        assert_eq!(t.get_pixel((5, 4)).unwrap(), 9);
+        assert_eq!(t.buffer.iter().filter(|c| **c != 9).count(), 2);
+    }
+
+    #[test]
+    fn sub_image_negative_dst() {
+        let mut t = target();
+        sub().blit_to(None, &mut t, Some(Rect::new(-1, -1, 0, 0)));
+
+        assert_eq!(t.get_pixel((0, 0)).unwrap(), 0);
+        assert_eq!(t.buffer.iter().filter(|c| **c != 9).count(), 1);
+    }
+
+    #[test]
+    fn sub_image_flip_moves_trimmed_margin() {
+        // The requested source starts one column left of the sub image, so
+        // one column is trimmed. Unflipped that column is the left margin;
+        // flipped it ends up on the right.
+        let src = Some(Rect::new(-1, 0, 2, 2));
+        let mut t = target();
+        sub().blit_with(src, &mut t, Some(Rect::new(3, 3, 0, 0)), &BlitOptions::new());
+        assert_eq!(t.get_pixel((3, 3)).unwrap(), 9);
+        assert_eq!(t.get_pixel((4, 3)).unwrap(), 0);
+        assert_eq!(t.get_pixel((4, 4)).unwrap(), 2);
+
+        let mut t = target();
+        sub().blit_with(src, &mut t, Some(Rect::new(3, 3, 0, 0)), &BlitOptions::new().flip_x(true));
+        assert_eq!(t.get_pixel((3, 3)).unwrap(), 0);
+        assert_eq!(t.get_pixel((3, 4)).unwrap(), 2);
+        assert_eq!(t.get_pixel((4, 3)).unwrap(), 9);
+
+        let mut t = target();
+        sub().blit_with(None, &mut t, Some(Rect::new(1, 1, 0, 0)), &BlitOptions::new().flip_x(true));
+        assert_eq!(t.get_pixel((1, 1)).unwrap(), 1);
+        assert_eq!(t.get_pixel((2, 1)).unwrap(), 0);
+        assert_eq!(t.get_pixel((1, 2)).unwrap(), 0);
+        assert_eq!(t.get_pixel((2, 2)).unwrap(), 2);
+    }
+
+    #[test]
+    fn grid_drops_partial_cells() {
+        let sheet = solid(10, 7, 0);
+        let cells: Vec<Rect> = SubImage::grid(sheet.clone(), Size::new(4, 3)).iter().map(|s| s.rect()).collect();
+        assert_eq!(cells, vec![Rect::new(0, 0, 4, 3), Rect::new(4, 0, 4, 3),
+                               Rect::new(0, 3, 4, 3), Rect::new(4, 3, 4, 3)]);
+        assert!(SubImage::grid(sheet, Size::new(0, 3)).is_empty());
     }
 }
@@ -3,6 +3,11 @@ pub mod screen;
 pub mod palettes;
 pub mod blit;
 pub mod sprite;
+pub mod tilemap;
+pub mod oam;
+pub mod font;
+pub mod animation;
+pub mod effects;
 
 const SCREEN_TOTAL_PIXELS: isize = 160 * 144;
 
@@ -0,0 +1,294 @@
+//! An OAM-like sprite layer. Sprites are queued each frame and drawn in an
+//! explicit order instead of whatever order entities happen to be visited.
+
+use std::cmp::Ordering;
+
+use ::gfx::image::{Image, ImageDelegate};
+use ::gfx::blit::BlitOptions;
+
+use ::math::Position;
+use ::math::rect::Rect;
+
+/// Sprites the hardware can show on one scanline.
+pub const SPRITES_PER_LINE: usize = 10;
+/// Sprites the hardware can hold in OAM.
+pub const MAX_SPRITES: usize = 40;
+
+pub struct OamEntry {
+    pub image: ImageDelegate,
+    pub position: Position,
+    pub options: BlitOptions,
+    /// Higher priorities are drawn over lower ones.
+    pub priority: i32,
+    /// Only show through backdrop pixels of the background and window, like
+    /// the GB's OBJ-to-BG priority bit.
+    pub behind_background: bool
+}
+
+impl OamEntry {
+    pub fn new(image: ImageDelegate, position: Position) -> OamEntry {
+        OamEntry {
+            image: image,
+            position: position,
+            options: BlitOptions::new(),
+            priority: 0,
+            behind_background: false
+        }
+    }
+
+    /// Where the sprite covers the screen once transformed.
+    pub fn bounds(&self) -> Rect {
+        let size = self.image.size();
+        let (w, h) = self.options.output_size(size.width, size.height);
+        Rect::new(self.position.x, self.position.y, w, h)
+    }
+}
+
+pub struct SpriteLayer {
+    entries: Vec<OamEntry>,
+    /// Enforce the hardware limits of 40 sprites and 10 per scanline.
+    pub authentic: bool,
+    /// In authentic mode, rotate which sprites come first in OAM every frame,
+    /// so sprites over the line limit flicker instead of vanishing.
+    pub flicker: bool,
+    /// The shade behind-background sprites show through. Defaults to the
+    /// lightest shade, GB color 0.
+    pub backdrop: u8,
+    frame: usize
+}
+
+/// Order two entries for drawing, back to front. Within a priority the GB
+/// puts the sprite with the lower X on top, then the one earlier in OAM.
+fn draw_order(a: &(usize, &OamEntry), b: &(usize, &OamEntry)) -> Ordering {
+    let (ai, ae) = *a;
+    let (bi, be) = *b;
+    match ae.priority.cmp(&be.priority) {
+        Ordering::Equal => match be.position.x.cmp(&ae.position.x) {
+            Ordering::Equal => bi.cmp(&ai),
+            o => o
+        },
+        o => o
+    }
+}
+
+impl SpriteLayer {
+    pub fn new() -> SpriteLayer {
+        SpriteLayer {
+            entries: Vec::new(),
+            authentic: false,
+            flicker: true,
+            backdrop: 3,
+            frame: 0
+        }
+    }
+
+    /// Queue a sprite for this frame. Returns its OAM index.
+    pub fn push(&mut self, entry: OamEntry) -> usize {
+        self.entries.push(entry);
+        self.entries.len() - 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Start a new frame, dropping every queued sprite.
+    pub fn clear(&mut self) -> () {
+        self.entries.clear();
+        self.frame = self.frame.wrapping_add(1);
+    }
+
+    /// Entries in OAM order for this frame.
+    fn oam_order(&self) -> Vec<(usize, &OamEntry)> {
+        let mut order: Vec<(usize, &OamEntry)> = self.entries.iter().enumerate().collect();
+        if self.authentic {
+            if self.flicker && !order.is_empty() {
+                let start = self.frame % order.len();
+                let mut rotated = order.split_off(start);
+                rotated.extend(order);
+                order = rotated;
+            }
+            order.truncate(MAX_SPRITES);
+        }
+        order
+    }
+
+    /// Draw the queued sprites over the composited background and window.
+    pub fn draw_to(&self, target: &mut Image) -> () {
+        let target_size = target.size();
+        let screen = Rect::new(0, 0, target_size.width, target_size.height);
+        let oam = self.oam_order();
+
+        // In authentic mode each scanline takes the first sprites in OAM
+        // that touch it.
+        let mut allowed: Vec<Vec<bool>> = Vec::with_capacity(oam.len());
+        for &(_, e) in oam.iter() {
+            allowed.push(vec![true; e.bounds().h() as usize]);
+        }
+        if self.authentic {
+            for y in 0..target_size.height as i32 {
+                let mut count = 0;
+                for (n, &(_, e)) in oam.iter().enumerate() {
+                    let b = e.bounds();
+                    if y < b.y() || y >= b.max_y() {
+                        continue;
+                    }
+                    if count >= SPRITES_PER_LINE {
+                        allowed[n][(y - b.y()) as usize] = false;
+                    }
+                    count += 1;
+                }
+            }
+        }
+
+        let mut order: Vec<(usize, (usize, &OamEntry))> = oam.into_iter().enumerate().collect();
+        order.sort_by(|a, b| draw_order(&a.1, &b.1));
+
+        let target_w = target_size.width as usize;
+        for (n, (_, e)) in order.into_iter() {
+            let b = e.bounds();
+            if b.clip(&screen).is_zero() {
+                continue;
+            }
+
+            // Look each pixel up in the source so the priority rules can be
+            // applied per pixel.
+            let (source, rect) = e.image.source();
+            let source_w = source.size().width as usize;
+            let (w, h) = (rect.w() as i32, rect.h() as i32);
+
+            for row in 0..b.h() as i32 {
+                let ty = b.y() + row;
+                if ty < 0 || ty >= target_size.height as i32 || !allowed[n][row as usize] {
+                    continue;
+                }
+                for col in 0..b.w() as i32 {
+                    let tx = b.x() + col;
+                    if tx < 0 || tx >= target_size.width as i32 {
+                        continue;
+                    }
+                    let (sx, sy) = e.options.source_offset(col, row, w, h);
+                    let pixel = source.buffer[(rect.y() + sy) as usize * source_w + (rect.x() + sx) as usize];
+                    let color = match e.options.map(pixel) {
+                        Some(c) => c,
+                        None => continue
+                    };
+                    let i = ty as usize * target_w + tx as usize;
+                    if e.behind_background && target.buffer[i] != self.backdrop {
+                        continue;
+                    }
+                    target.buffer[i] = color;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::{SpriteLayer, OamEntry};
+    use ::gfx::image::{Image, ImageDelegate, SubImage, TRANSPARENT};
+    use ::gfx::blit::{Blit, Rotation};
+    use ::math::Position;
+    use ::math::rect::Rect;
+    use ::testing::solid;
+
+    fn sprite(shade: u8, x: i32, y: i32, w: u32) -> OamEntry {
+        OamEntry::new(ImageDelegate::ImageBuf(solid(w, 1, shade)), Position::new(x, y))
+    }
+
+    /// Queue eleven one pixel sprites along the top line.
+    fn fill_line(layer: &mut SpriteLayer) -> () {
+        for x in 0..11 {
+            layer.push(sprite(0, x, 0, 1));
+        }
+    }
+
+    fn draw(layer: &SpriteLayer) -> Image {
+        let mut target = Image::new((16, 2), 3u8);
+        layer.draw_to(&mut target);
+        target
+    }
+
+    #[test]
+    fn eleventh_sprite_on_a_line_is_hidden() {
+        let mut layer = SpriteLayer::new();
+        fill_line(&mut layer);
+        assert_eq!(&draw(&layer).buffer[..12], &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3]);
+
+        layer.authentic = true;
+        layer.flicker = false;
+        assert_eq!(&draw(&layer).buffer[..12], &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 3]);
+    }
+
+    #[test]
+    fn flicker_rotates_the_hidden_sprite() {
+        let mut layer = SpriteLayer::new();
+        layer.authentic = true;
+        fill_line(&mut layer);
+        let first = draw(&layer);
+        assert_eq!((first.buffer[0], first.buffer[10]), (0, 3));
+
+        layer.clear();
+        fill_line(&mut layer);
+        let second = draw(&layer);
+        assert_eq!((second.buffer[0], second.buffer[10]), (3, 0));
+    }
+
+    #[test]
+    fn lower_x_wins_then_lower_index() {
+        let mut layer = SpriteLayer::new();
+        layer.push(sprite(2, 2, 0, 4));
+        layer.push(sprite(1, 0, 0, 4));
+        assert_eq!(&draw(&layer).buffer[..6], &[1, 1, 1, 1, 2, 2]);
+
+        let mut layer = SpriteLayer::new();
+        layer.push(sprite(1, 0, 0, 4));
+        layer.push(sprite(2, 0, 0, 4));
+        assert_eq!(&draw(&layer).buffer[..4], &[1, 1, 1, 1]);
+
+        // Priority beats both.
+        let mut layer = SpriteLayer::new();
+        layer.push(sprite(1, 0, 0, 4));
+        let mut top = sprite(2, 2, 0, 4);
+        top.priority = 1;
+        layer.push(top);
+        assert_eq!(&draw(&layer).buffer[..6], &[1, 1, 2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn behind_background_only_shows_on_backdrop() {
+        let mut layer = SpriteLayer::new();
+        let mut entry = sprite(1, 0, 0, 3);
+        entry.behind_background = true;
+        layer.push(entry);
+
+        let mut target = Image::new((4, 1), 3u8);
+        target.buffer[1] = 0;
+        target.buffer[2] = 2;
+        layer.draw_to(&mut target);
+        assert_eq!(target.buffer, vec![1, 0, 2, 3]);
+    }
+
+    #[test]
+    fn transformed_sub_images_draw_like_a_blit() {
+        // a 3x2 cell of a wider sheet, with one transparent pixel
+        let mut sheet = Image::new((5, 2), 0u8);
+        sheet.buffer = vec![3, 0, 1, 2, 3,
+                            3, TRANSPARENT, 2, 1, 3];
+        let cell = ImageDelegate::ImageRef(SubImage::new(Rc::new(sheet), Rect::new(1, 0, 3, 2)));
+
+        let mut entry = OamEntry::new(cell.clone(), Position::new(-1, 0));
+        entry.options = entry.options.flip_x(true).rotation(Rotation::Cw90).palette([1, 2, 0, 3]);
+        let mut expected = Image::new((4, 3), 3u8);
+        cell.blit_with(None, &mut expected, Some(Rect::new(-1, 0, 0, 0)), &entry.options);
+
+        let mut layer = SpriteLayer::new();
+        layer.push(entry);
+        let mut target = Image::new((4, 3), 3u8);
+        layer.draw_to(&mut target);
+        assert_eq!(target.buffer, expected.buffer);
+    }
+}
@@ -1,5 +1,27 @@
 use ::gfx::Color;
 
+/// Four output colors for the shades, darkest first.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    pub colors: [Color; 4]
+}
+
+impl Palette {
+    pub fn new(name: &str, colors: [Color; 4]) -> Palette {
+        Palette {
+            name: name.to_string(),
+            colors: colors
+        }
+    }
+
+    /// A palette from `0xRRGGBB` values, darkest first.
+    fn rgb(name: &str, colors: [u32; 4]) -> Palette {
+        let c = |v: u32| [(v >> 16) as u8, (v >> 8) as u8, v as u8, 255];
+        Palette::new(name, [c(colors[0]), c(colors[1]), c(colors[2]), c(colors[3])])
+    }
+}
+
 #[inline]
 pub fn default_colors() -> [Color; 4] {
     [
@@ -9,3 +31,47 @@ pub fn default_colors() -> [Color; 4] {
         [155, 188, 15, 255]
     ]
 }
+
+pub fn dmg_green() -> Palette {
+    Palette::new("DMG green", default_colors())
+}
+
+pub fn pocket_grey() -> Palette {
+    Palette::rgb("Pocket grey", [0x2a2a2a, 0x555c4a, 0x8c926b, 0xc5caa4])
+}
+
+/// The background palettes a GBC picks for original GB games from the
+/// button combo held during the boot logo.
+pub fn gbc_boot() -> Vec<Palette> {
+    vec![
+        Palette::rgb("GBC up", [0x000000, 0x843100, 0xffad63, 0xffffff]),
+        Palette::rgb("GBC up+A", [0x000000, 0x943a3a, 0xff8484, 0xffffff]),
+        Palette::rgb("GBC up+B", [0x5a3108, 0x846b29, 0xce9c84, 0xffe6c5]),
+        Palette::rgb("GBC left", [0x000000, 0x0000fe, 0x65a49b, 0xffffff]),
+        Palette::rgb("GBC left+A", [0x000000, 0x53528c, 0x8b8cde, 0xffffff]),
+        Palette::rgb("GBC left+B", [0x000000, 0x525252, 0xa5a5a5, 0xffffff]),
+        Palette::rgb("GBC down", [0x000000, 0x9394fe, 0xfe9494, 0xffffa5]),
+        Palette::rgb("GBC down+A", [0x000000, 0xfe0000, 0xffff00, 0xffffff]),
+        Palette::rgb("GBC down+B", [0x000000, 0x7d4900, 0xffff00, 0xffffff]),
+        Palette::rgb("GBC right", [0x000000, 0xff4200, 0x51ff00, 0xffffff]),
+        Palette::rgb("GBC right+A", [0x000000, 0x0063c5, 0x7bff31, 0xffffff]),
+        Palette::rgb("GBC right+B", [0xffffff, 0xffde00, 0x008486, 0x000000])
+    ]
+}
+
+/// Palettes with widely spaced shades for players who find the DMG colors
+/// hard to tell apart.
+pub fn high_contrast() -> Vec<Palette> {
+    vec![
+        Palette::rgb("High contrast", [0x000000, 0x606060, 0xb8b8b8, 0xffffff]),
+        Palette::rgb("High contrast yellow", [0x000000, 0x0050c8, 0xe8c000, 0xffffa0])
+    ]
+}
+
+/// Every built-in palette, DMG green first.
+pub fn presets() -> Vec<Palette> {
+    let mut all = vec![dmg_green(), pocket_grey()];
+    all.extend(gbc_boot());
+    all.extend(high_contrast());
+    all
+}
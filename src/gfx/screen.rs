@@ -1,18 +1,44 @@
-use super::image::Image;
+use super::image::{Image, TRANSPARENT};
+use super::blit::Blit;
+use super::oam::SpriteLayer;
+use super::effects::{PaletteEffect, IDENTITY};
 use super::Color;
 
+use ::math::Position;
+use ::math::rect::Rect;
 use ::math::size::Size;
 
 use std::convert::From;
 
+/// The GB's window: a layer over the background that doesn't scroll, placed
+/// at WX/WY. Used for HUDs and text boxes.
+pub struct Window {
+    pub image: Image,
+    pub position: Position,
+    pub visible: bool
+}
+
+/// Layers are composited into `image` in a fixed order: background, window,
+/// then sprites. The background and window keep their contents between
+/// frames; the sprite layer is emptied at the start of every frame.
+///
+/// Each frame starts with `image` set to the background, so drawers that
+/// draw straight into `image` still work and land under the window and
+/// sprites.
 pub struct Screen {
     pub image: Image,
-    pub colors: [Color; 4]
+    pub colors: [Color; 4],
+    pub background: Image,
+    pub window: Window,
+    pub sprites: SpriteLayer,
+    /// The shade each shade in `image` is shown as, like the GB's BGP.
+    pub shade_map: [u8; 4],
+    effect: Option<PaletteEffect>
 }
 
 impl Screen {
     pub fn new() -> Screen {
-        let image = Image::new((160, 144), 0u8);
+        let image = Image::new((160, 144), 3u8);
         let colors = [
             [0, 0, 0, 0],
             [0, 0, 0, 0],
@@ -22,7 +48,102 @@ impl Screen {
 
         Screen {
             image: image,
-            colors: colors
+            colors: colors,
+            background: Image::new((160, 144), 3u8),
+            window: Window {
+                image: Image::new((160, 144), TRANSPARENT),
+                position: Position::new(0, 0),
+                visible: false
+            },
+            sprites: SpriteLayer::new(),
+            shade_map: IDENTITY,
+            effect: None
+        }
+    }
+
+    #[inline]
+    pub fn size(&self) -> Size {
+        self.image.size()
+    }
+
+    /// The output color for a shade index in the screen buffer.
+    #[inline]
+    pub fn color(&self, shade: u8) -> Color {
+        let mapped = self.shade_map[::std::cmp::min(shade, 3) as usize];
+        self.colors[::std::cmp::min(mapped, 3) as usize]
+    }
+
+    /// Start a palette effect, replacing any that's running.
+    pub fn start_effect(&mut self, effect: PaletteEffect) -> () {
+        self.shade_map = effect.shade_map();
+        self.effect = Some(effect);
+    }
+
+    /// Stop the running effect and show the normal palette.
+    pub fn stop_effect(&mut self) -> () {
+        self.effect = None;
+        self.shade_map = IDENTITY;
+    }
+
+    #[inline]
+    pub fn effect(&self) -> Option<&PaletteEffect> {
+        self.effect.as_ref()
+    }
+
+    /// Step the running effect one frame. A finished effect's last shade map
+    /// stays in place, so a fade out keeps the screen dark. Returns the
+    /// effect's finish callback for the caller to run once it no longer
+    /// holds the screen.
+    pub fn update_effect(&mut self) -> Option<Box<FnMut()>> {
+        let (callback, finished) = match self.effect {
+            Some(ref mut e) => {
+                let callback = e.advance();
+                self.shade_map = e.shade_map();
+                (callback, e.is_finished())
+            },
+            None => return None
+        };
+        if finished {
+            self.effect = None;
+        }
+        callback
+    }
+
+    /// Reset `image` to the background and empty the sprite layer for a new
+    /// frame.
+    pub fn begin_frame(&mut self) -> () {
+        self.image.buffer.clone_from(&self.background.buffer);
+        self.sprites.clear();
+    }
+
+    /// Draw the window and sprites over `image`.
+    pub fn composite(&mut self) -> () {
+        if self.window.visible {
+            let p = self.window.position;
+            self.window.image.blit_to(None, &mut self.image, Some(Rect::new(p.x, p.y, 0, 0)));
         }
+
+        self.sprites.draw_to(&mut self.image);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drawing_into_image_survives_composite() {
+        let mut screen = Screen::new();
+        assert!(screen.background.buffer.iter().all(|&s| s == 3));
+
+        screen.begin_frame();
+        screen.image.buffer[0] = 1;
+        screen.window.image.buffer[1] = 2;
+        screen.window.visible = true;
+        screen.composite();
+        assert_eq!(&screen.image.buffer[..3], &[1, 2, 3]);
+
+        screen.begin_frame();
+        assert_eq!(screen.image.buffer[0], 3);
     }
 }
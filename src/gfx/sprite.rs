@@ -1,25 +1,42 @@
 use ::gfx::image::{ImageDelegate, Image, SubImage};
-use ::gfx::blit::Blit;
+use ::gfx::blit::{Blit, BlitOptions};
 
 use ::math::Position;
 use ::math::rect::Rect;
+use ::math::size::Size;
 
 pub struct Sprite {
     image: ImageDelegate,
-    offset: Position
+    offset: Position,
+    pub options: BlitOptions
 }
 
 impl Sprite {
     pub fn new(im: ImageDelegate, offset: Position) -> Self {
         Sprite {
             image: im,
-            offset: offset
+            offset: offset,
+            options: BlitOptions::new()
+        }
+    }
+
+    pub fn with_options(im: ImageDelegate, offset: Position, options: BlitOptions) -> Self {
+        Sprite {
+            image: im,
+            offset: offset,
+            options: options
         }
     }
 }
 
 impl Blit for Sprite {
+    /// Draws with the sprite's own options.
     fn blit_to(&self, src: Option<Rect>, target: &mut Image, dst: Option<Rect>) -> () {
+        self.blit_with(src, target, dst, &self.options)
+    }
+
+    /// Draws with `options` in place of the sprite's own.
+    fn blit_with(&self, src: Option<Rect>, target: &mut Image, dst: Option<Rect>, options: &BlitOptions) -> () {
         // Offset the dst by our own offset
         let dst: Rect = if let Some(r) = dst {
             Rect::new(r.x() + self.offset.x, r.y() + self.offset.y, r.w(), r.h())
@@ -27,6 +44,23 @@ impl Blit for Sprite {
             Rect::new(self.offset.x, self.offset.y, 0, 0)
         };
 
-        self.image.blit_to(src, target, Some(dst));
+        self.image.blit_with(src, target, Some(dst), options);
+    }
+}
+
+impl Sprite {
+    #[inline]
+    pub fn size(&self) -> Size {
+        self.image.size()
+    }
+
+    #[inline]
+    pub fn image(&self) -> &ImageDelegate {
+        &self.image
+    }
+
+    #[inline]
+    pub fn offset(&self) -> Position {
+        self.offset
     }
 }
@@ -0,0 +1,202 @@
+use std::rc::Rc;
+
+use ::gfx::image::{Image, SubImage};
+use ::gfx::blit::{Blit, BlitOptions, Rotation};
+
+use ::math::Position;
+use ::math::rect::Rect;
+use ::math::size::Size;
+
+/// Width and height of a tile in pixels.
+pub const TILE_SIZE: u32 = 8;
+
+/// How one cell's tile is turned, like the flip bits of a GBC background
+/// attribute. Flips are applied before the rotation, as in `BlitOptions`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TileFlip {
+    pub flip_x: bool,
+    pub flip_y: bool,
+    pub rotation: Rotation
+}
+
+impl TileFlip {
+    pub fn none() -> TileFlip {
+        TileFlip { flip_x: false, flip_y: false, rotation: Rotation::None }
+    }
+}
+
+/// A grid of tile indices into a tileset, drawn like the GB background:
+/// `scroll` is the map pixel shown at the top left of the target (SCX/SCY).
+pub struct Tilemap {
+    tileset: Vec<SubImage>,
+    width: u32,
+    height: u32,
+    tiles: Vec<Option<u16>>,
+    flips: Vec<TileFlip>,
+    pub scroll: Position,
+    /// Repeat the map past its edges instead of leaving them undrawn.
+    pub wrap: bool,
+    /// Palette and transparency for every tile. A cell's `TileFlip` takes
+    /// the place of the flips and rotation here.
+    pub options: BlitOptions
+}
+
+/// Slice a tileset image into 8x8 tiles.
+pub fn tileset(image: Rc<Image>) -> Vec<SubImage> {
+    SubImage::grid(image, Size::new(TILE_SIZE, TILE_SIZE))
+}
+
+impl Tilemap {
+    /// An empty map `width` by `height` tiles.
+    pub fn new(tileset: Vec<SubImage>, width: u32, height: u32) -> Tilemap {
+        Tilemap {
+            tileset: tileset,
+            width: width,
+            height: height,
+            tiles: Size::new(width, height).buffer(None),
+            flips: Size::new(width, height).buffer(TileFlip::none()),
+            scroll: Position::new(0, 0),
+            wrap: true,
+            options: BlitOptions::new()
+        }
+    }
+
+    /// A map from row first tile indices.
+    pub fn from_tiles(tileset: Vec<SubImage>, width: u32, height: u32, tiles: Vec<Option<u16>>) -> Result<Tilemap, String> {
+        if tiles.len() != (width * height) as usize {
+            return Err(format!("Expected {} tiles, got {}", width * height, tiles.len()));
+        }
+
+        let mut map = Tilemap::new(tileset, width, height);
+        map.tiles = tiles;
+        Ok(map)
+    }
+
+    /// Set every cell's flips, row first.
+    pub fn set_flips(&mut self, flips: Vec<TileFlip>) -> Result<(), String> {
+        if flips.len() != self.tiles.len() {
+            return Err(format!("Expected {} flips, got {}", self.tiles.len(), flips.len()));
+        }
+        self.flips = flips;
+        Ok(())
+    }
+
+    #[inline]
+    pub fn width(&self) -> u32 { self.width }
+    #[inline]
+    pub fn height(&self) -> u32 { self.height }
+
+    /// Size of the whole map in pixels.
+    #[inline]
+    pub fn pixel_size(&self) -> Size {
+        Size::new(self.width * TILE_SIZE, self.height * TILE_SIZE)
+    }
+
+    #[inline]
+    pub fn tileset(&self) -> &[SubImage] {
+        &self.tileset
+    }
+
+    pub fn tile(&self, x: u32, y: u32) -> Option<u16> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        self.tiles[(y * self.width + x) as usize]
+    }
+
+    pub fn set_tile(&mut self, x: u32, y: u32, tile: Option<u16>) -> Result<(), String> {
+        if x >= self.width || y >= self.height {
+            return Err("error out of range".to_string());
+        }
+        self.tiles[(y * self.width + x) as usize] = tile;
+        Ok(())
+    }
+
+    pub fn flip(&self, x: u32, y: u32) -> TileFlip {
+        if x >= self.width || y >= self.height {
+            return TileFlip::none();
+        }
+        self.flips[(y * self.width + x) as usize]
+    }
+
+    pub fn set_flip(&mut self, x: u32, y: u32, flip: TileFlip) -> Result<(), String> {
+        if x >= self.width || y >= self.height {
+            return Err("error out of range".to_string());
+        }
+        self.flips[(y * self.width + x) as usize] = flip;
+        Ok(())
+    }
+
+    /// Draw the visible part of the map over the whole target. Empty cells
+    /// leave the target untouched.
+    pub fn draw_to(&self, target: &mut Image) -> () {
+        if self.width == 0 || self.height == 0 {
+            return;
+        }
+
+        let map_size = self.pixel_size();
+        let (mut sx, mut sy) = (self.scroll.x, self.scroll.y);
+        if self.wrap {
+            sx = ((sx % map_size.width as i32) + map_size.width as i32) % map_size.width as i32;
+            sy = ((sy % map_size.height as i32) + map_size.height as i32) % map_size.height as i32;
+        }
+
+        let ts = TILE_SIZE as i32;
+        // Floor division, so negative scrolls start on the right tile.
+        let first_col = if sx < 0 { (sx - ts + 1) / ts } else { sx / ts };
+        let first_row = if sy < 0 { (sy - ts + 1) / ts } else { sy / ts };
+        let (fine_x, fine_y) = (sx - first_col * ts, sy - first_row * ts);
+
+        let target_size = target.size();
+        let columns = (target_size.width + TILE_SIZE - 1) / TILE_SIZE + 1;
+        let rows = (target_size.height + TILE_SIZE - 1) / TILE_SIZE + 1;
+
+        for row in 0..rows as i32 {
+            for col in 0..columns as i32 {
+                let (mut mx, mut my) = (first_col + col, first_row + row);
+                if self.wrap {
+                    mx %= self.width as i32;
+                    my %= self.height as i32;
+                } else if mx < 0 || my < 0 {
+                    continue;
+                }
+
+                let tile = match self.tile(mx as u32, my as u32) {
+                    Some(t) => t,
+                    None => continue
+                };
+                if let Some(sub) = self.tileset.get(tile as usize) {
+                    let flip = self.flip(mx as u32, my as u32);
+                    let mut options = self.options;
+                    options.flip_x = flip.flip_x;
+                    options.flip_y = flip.flip_y;
+                    options.rotation = flip.rotation;
+
+                    let dst = Rect::new(col * ts - fine_x, row * ts - fine_y, 0, 0);
+                    sub.blit_with(None, target, Some(dst), &options);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn cells_draw_with_their_flips() {
+        // one tile whose shade is 1 only at its top right pixel
+        let mut image = Image::new((8, 8), 0u8);
+        image.buffer[7] = 1;
+        let mut map = Tilemap::from_tiles(tileset(Rc::new(image)), 2, 1, vec![Some(0), Some(0)]).unwrap();
+        map.set_flip(1, 0, TileFlip { flip_x: true, flip_y: true, rotation: Rotation::None }).unwrap();
+
+        let mut target = Image::new((16, 8), 2u8);
+        map.draw_to(&mut target);
+        let lit: Vec<usize> = (0..target.buffer.len()).filter(|&i| target.buffer[i] == 1).collect();
+        assert_eq!(lit, vec![7, 7 * 16 + 8]);
+        assert!(map.set_flips(vec![TileFlip::none()]).is_err());
+    }
+}
@@ -1,3 +1,5 @@
+use sdl2::keyboard::Scancode;
+
 #[derive(Clone, Copy, Debug)]
 pub struct InputState {
     pub left: PressedState,
@@ -25,6 +27,33 @@ impl InputState {
         }
     }
 
+    pub fn get(&self, button: Button) -> PressedState {
+        match button {
+            Button::Left => self.left,
+            Button::Right => self.right,
+            Button::Up => self.up,
+            Button::Down => self.down,
+            Button::A => self.a,
+            Button::B => self.b,
+            Button::Start => self.start,
+            Button::Select => self.select
+        }
+    }
+
+    pub fn set(&mut self, button: Button, state: PressedState) -> () {
+        let field = match button {
+            Button::Left => &mut self.left,
+            Button::Right => &mut self.right,
+            Button::Up => &mut self.up,
+            Button::Down => &mut self.down,
+            Button::A => &mut self.a,
+            Button::B => &mut self.b,
+            Button::Start => &mut self.start,
+            Button::Select => &mut self.select
+        };
+        *field = state;
+    }
+
     pub fn update(&mut self) {
         self.left.update();
         self.right.update();
@@ -54,3 +83,308 @@ impl PressedState {
         };
     }
 }
+
+/// The buttons of a GB.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Button {
+    Left,
+    Right,
+    Up,
+    Down,
+    A,
+    B,
+    Start,
+    Select
+}
+
+impl Button {
+    pub fn all() -> [Button; 8] {
+        use self::Button::*;
+        [Up, Down, Left, Right, A, B, Start, Select]
+    }
+
+    /// The name used in the bindings config file.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Button::Left => "left",
+            Button::Right => "right",
+            Button::Up => "up",
+            Button::Down => "down",
+            Button::A => "a",
+            Button::B => "b",
+            Button::Start => "start",
+            Button::Select => "select"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Button> {
+        Button::all().iter().cloned().find(|b| b.name() == name)
+    }
+}
+
+/// Which keys press which buttons. A button can have any number of keys,
+/// but each key presses at most one button. Keys can also be given to the
+/// palette cycle instead of a button.
+#[derive(Clone, Debug)]
+pub struct KeyBindings {
+    keys: Vec<(Scancode, Button)>,
+    palette_keys: Vec<Scancode>,
+    /// Waiting to give the next key press to this button.
+    capture: Option<Button>,
+    capture_cancelled: bool
+}
+
+impl KeyBindings {
+    /// Arrows, Z for A, X for B, Return for Start and right Shift for Select.
+    /// P cycles the palette.
+    pub fn new() -> KeyBindings {
+        let mut bindings = KeyBindings::empty();
+        bindings.bind(Button::Left, Scancode::Left);
+        bindings.bind(Button::Right, Scancode::Right);
+        bindings.bind(Button::Up, Scancode::Up);
+        bindings.bind(Button::Down, Scancode::Down);
+        bindings.bind(Button::A, Scancode::Z);
+        bindings.bind(Button::B, Scancode::X);
+        bindings.bind(Button::Start, Scancode::Return);
+        bindings.bind(Button::Select, Scancode::RShift);
+        bindings.bind_palette(Scancode::P);
+        bindings
+    }
+
+    pub fn empty() -> KeyBindings {
+        KeyBindings {
+            keys: Vec::new(),
+            palette_keys: Vec::new(),
+            capture: None,
+            capture_cancelled: false
+        }
+    }
+
+    pub fn button(&self, scancode: Scancode) -> Option<Button> {
+        self.keys.iter().find(|&&(s, _)| s == scancode).map(|&(_, b)| b)
+    }
+
+    pub fn keys(&self, button: Button) -> Vec<Scancode> {
+        self.keys.iter().filter(|&&(_, b)| b == button).map(|&(s, _)| s).collect()
+    }
+
+    /// Add a key to a button, taking it away from any other button or the
+    /// palette cycle.
+    pub fn bind(&mut self, button: Button, scancode: Scancode) -> () {
+        self.unbind(scancode);
+        self.keys.push((scancode, button));
+    }
+
+    pub fn unbind(&mut self, scancode: Scancode) -> () {
+        self.keys.retain(|&(s, _)| s != scancode);
+        self.palette_keys.retain(|&s| s != scancode);
+    }
+
+    /// Make a key cycle the palette, taking it away from any button.
+    pub fn bind_palette(&mut self, scancode: Scancode) -> () {
+        self.unbind(scancode);
+        self.palette_keys.push(scancode);
+    }
+
+    #[inline]
+    pub fn palette_keys(&self) -> &[Scancode] {
+        &self.palette_keys
+    }
+
+    #[inline]
+    pub fn is_palette_key(&self, scancode: Scancode) -> bool {
+        self.palette_keys.contains(&scancode)
+    }
+
+    /// Remove every key from a button.
+    pub fn clear(&mut self, button: Button) -> () {
+        self.keys.retain(|&(_, b)| b != button);
+    }
+
+    /// Make the next key press the only key for `button`. Escape cancels.
+    pub fn capture(&mut self, button: Button) -> () {
+        self.capture = Some(button);
+        self.capture_cancelled = false;
+    }
+
+    #[inline]
+    pub fn capturing(&self) -> Option<Button> {
+        self.capture
+    }
+
+    /// Whether the last capture was cancelled, clearing the flag.
+    pub fn take_cancelled(&mut self) -> bool {
+        let cancelled = self.capture_cancelled;
+        self.capture_cancelled = false;
+        cancelled
+    }
+
+    /// Give a key press to a pending capture. Returns whether it was used.
+    /// If the key was the last one of another button or the palette cycle,
+    /// that gets the capturing button's old keys, so nothing is left without
+    /// a key.
+    pub fn capture_key(&mut self, scancode: Scancode) -> bool {
+        let button = match self.capture.take() {
+            Some(b) => b,
+            None => return false
+        };
+        if scancode == Scancode::Escape {
+            self.capture_cancelled = true;
+            return true;
+        }
+
+        let owner = self.button(scancode);
+        let was_palette = self.is_palette_key(scancode);
+        let old = self.keys(button);
+        self.clear(button);
+        self.bind(button, scancode);
+
+        match owner {
+            Some(b) if b != button && self.keys(b).is_empty() => {
+                for &key in old.iter() {
+                    self.bind(b, key);
+                }
+            },
+            _ if was_palette && self.palette_keys.is_empty() => {
+                for &key in old.iter() {
+                    self.bind_palette(key);
+                }
+            },
+            _ => ()
+        }
+        true
+    }
+
+    /// Read bindings written by `to_config`. Buttons the text doesn't
+    /// mention keep their default keys.
+    pub fn from_config(text: &str) -> Result<KeyBindings, String> {
+        let mut bindings = KeyBindings::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.splitn(2, '=');
+            let name = parts.next().unwrap_or("").trim();
+            let keys = try!(parts.next().ok_or(format!("Line {}: expected <button> = <key>, ...", n + 1)));
+            let button = if name == "palette" {
+                bindings.palette_keys.clear();
+                None
+            } else {
+                let button = try!(Button::from_name(name).ok_or(format!("Line {}: unknown button \"{}\"", n + 1, name)));
+                bindings.clear(button);
+                Some(button)
+            };
+
+            for key in keys.split(',').map(|k| k.trim()).filter(|k| !k.is_empty()) {
+                let scancode = try!(Scancode::from_name(key).ok_or(format!("Line {}: unknown key \"{}\"", n + 1, key)));
+                match button {
+                    Some(b) => bindings.bind(b, scancode),
+                    None => bindings.bind_palette(scancode)
+                }
+            }
+        }
+        Ok(bindings)
+    }
+
+    /// One `<button> = <key>, <key>` line per button, then a `palette` line,
+    /// using SDL key names.
+    pub fn to_config(&self) -> String {
+        let mut text = "# <button> = <key>, <key>, ...\n".to_string();
+        for button in Button::all().iter() {
+            let names: Vec<&str> = self.keys(*button).iter().map(|s| s.name()).collect();
+            text.push_str(&format!("{} = {}\n", button.name(), names.join(", ")));
+        }
+        let names: Vec<&str> = self.palette_keys.iter().map(|s| s.name()).collect();
+        text.push_str(&format!("palette = {}\n", names.join(", ")));
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sdl2::keyboard::Scancode;
+    use super::{Button, KeyBindings};
+
+    #[test]
+    fn bind_moves_key_between_buttons() {
+        let mut bindings = KeyBindings::new();
+        bindings.bind(Button::A, Scancode::X);
+        assert_eq!(bindings.button(Scancode::X), Some(Button::A));
+        assert_eq!(bindings.keys(Button::A), vec![Scancode::Z, Scancode::X]);
+        assert!(bindings.keys(Button::B).is_empty());
+    }
+
+    #[test]
+    fn capture_replaces_keys() {
+        let mut bindings = KeyBindings::new();
+        assert!(!bindings.capture_key(Scancode::Q));
+        bindings.capture(Button::A);
+        assert!(bindings.capture_key(Scancode::Q));
+        assert_eq!(bindings.keys(Button::A), vec![Scancode::Q]);
+        assert_eq!(bindings.capturing(), None);
+
+        bindings.capture(Button::B);
+        assert!(bindings.capture_key(Scancode::Escape));
+        assert!(bindings.take_cancelled());
+        assert_eq!(bindings.keys(Button::B), vec![Scancode::X]);
+    }
+
+    #[test]
+    fn config_errors() {
+        assert!(KeyBindings::from_config("jump = Z\n").is_err());
+        assert!(KeyBindings::from_config("a Z\n").is_err());
+        let empty = KeyBindings::from_config("# nothing\n\na =\n").unwrap();
+        assert!(empty.keys(Button::A).is_empty());
+    }
+
+    #[test]
+    fn config_keeps_defaults_for_missing_buttons() {
+        let bindings = KeyBindings::from_config("a = Space, K\nb = Z\n").unwrap();
+        assert_eq!(bindings.keys(Button::A), vec![Scancode::Space, Scancode::K]);
+        assert_eq!(bindings.keys(Button::B), vec![Scancode::Z]);
+        assert_eq!(bindings.keys(Button::Start), vec![Scancode::Return]);
+        assert_eq!(bindings.keys(Button::Left), vec![Scancode::Left]);
+
+        let round_trip = KeyBindings::from_config(&bindings.to_config()).unwrap();
+        for button in Button::all().iter() {
+            assert_eq!(round_trip.keys(*button), bindings.keys(*button));
+        }
+    }
+
+    #[test]
+    fn capturing_a_taken_key_swaps_it() {
+        // Every button takes the key of another one in turn.
+        let mut bindings = KeyBindings::new();
+        let keys = [Scancode::Z, Scancode::X, Scancode::Return, Scancode::RShift,
+                    Scancode::Up, Scancode::Down, Scancode::P, Scancode::Left];
+        for (button, key) in Button::all().iter().zip(keys.iter()) {
+            bindings.capture(*button);
+            assert!(bindings.capture_key(*key));
+            assert_eq!(bindings.keys(*button), vec![*key]);
+            for b in Button::all().iter() {
+                assert!(!bindings.keys(*b).is_empty(), "{:?} has no key", b);
+            }
+            assert!(!bindings.palette_keys().is_empty());
+        }
+        assert_eq!(bindings.keys(Button::Up), vec![Scancode::Z]);
+        assert_eq!(bindings.keys(Button::A), vec![Scancode::Up]);
+        assert_eq!(bindings.palette_keys(), &[Scancode::Right]);
+    }
+
+    #[test]
+    fn palette_keys_in_config() {
+        let bindings = KeyBindings::from_config("palette = F1, F2
+").unwrap();
+        assert_eq!(bindings.palette_keys(), &[Scancode::F1, Scancode::F2]);
+        assert_eq!(bindings.button(Scancode::P), None);
+
+        let round_trip = KeyBindings::from_config(&bindings.to_config()).unwrap();
+        assert_eq!(round_trip.palette_keys(), bindings.palette_keys());
+
+        let mut bindings = KeyBindings::new();
+        bindings.bind(Button::A, Scancode::P);
+        assert!(!bindings.is_palette_key(Scancode::P));
+    }
+}
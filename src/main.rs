@@ -2,33 +2,143 @@
 #![allow(unused_variables)]
 #![allow(unused_imports)]
 
-#![feature(unboxed_closures)]
+// Written in 2015 style: `try!`, bare trait objects, explicit `-> ()` and
+// `field: field` initializers and `&(ref a, ref b)` patterns are used throughout.
+#![allow(deprecated, bare_trait_objects)]
+#![allow(clippy::unused_unit, clippy::redundant_field_names, clippy::redundant_static_lifetimes,
+         clippy::needless_borrowed_reference)]
+// Newer std APIs (`clamp`, `div_ceil`, `strip_prefix`, ...) than this code
+// is written against, and `Into` impls and `+ 0` offsets kept from the start.
+#![allow(clippy::manual_clamp, clippy::manual_div_ceil, clippy::manual_is_multiple_of, clippy::manual_strip,
+         clippy::implicit_saturating_sub, clippy::unnecessary_map_or, clippy::legacy_numeric_constants,
+         clippy::from_over_into, clippy::identity_op)]
 
 #[macro_use] extern crate log;
 extern crate env_logger;
 extern crate sdl2;
 extern crate find_folder;
 extern crate image;
+extern crate rustc_serialize;
+extern crate xml;
 
 mod game;
 mod input;
 mod math;
 mod gfx;
 mod assets;
+mod backend;
+#[cfg(test)]
+mod testing;
+
+use std::rc::Rc;
+use std::cell::RefCell;
 
 use game::Game;
+use game::world::World;
+use game::scene::{WorldScene, SceneChange, Transition};
+use game::entitybuilder::EntityBuilder;
+use game::rebind::RebindScene;
+use gfx::image::ImageDelegate;
+use gfx::font::Font;
+use input::{KeyBindings, PressedState};
+use math::Vector;
+use math::size::Size;
+use backend::sdl::SdlBackend;
+use backend::headless::HeadlessBackend;
 
 use log::LogLevel;
 
+/// `--headless <frames>` runs the given number of frames without a window.
+fn headless_frames() -> Option<u64> {
+    let mut args = std::env::args().skip_while(|a| a != "--headless");
+    args.next().and_then(|_| args.next()).and_then(|n| n.parse().ok())
+}
+
+/// Where key bindings are saved, in the assets folder.
+const BINDINGS_FILE: &'static str = "keybindings.cfg";
+
+/// `font.png` from the assets folder if there is one, otherwise the built-in
+/// font.
+fn load_font() -> Font {
+    let path = assets::asset_path("font.png");
+    if !path.exists() {
+        return Font::builtin();
+    }
+    match assets::load_font(path, Size::new(8, 8), ' ') {
+        Ok(f) => f,
+        Err(e) => {
+            warn!("Using the built-in font: {}", e);
+            Font::builtin()
+        }
+    }
+}
+
+/// A world with the test image in the corner. Select rebinds the keys.
+fn test_scene(bindings: Rc<RefCell<KeyBindings>>) -> Result<WorldScene, String> {
+    let im = try!(assets::load_image(assets::asset_path("test-img.png")));
+
+    let mut world = World::new();
+    EntityBuilder::new(&mut world)
+        .position(Vector::new(0.0, 0.0))
+        .sprite(ImageDelegate::ImageBuf(Rc::new(im)))
+        .finish();
+
+    let font = Rc::new(load_font());
+    Ok(WorldScene::new(world).with_controller(move |_, input| {
+        if input.select == PressedState::Pressed {
+            let rebind = RebindScene::new(bindings.clone(), font.clone()).save_to(assets::asset_path(BINDINGS_FILE));
+            SceneChange::Push(Box::new(rebind), Transition::Cut)
+        } else {
+            SceneChange::Continue
+        }
+    }))
+}
+
+/// `--vsync` waits for the display instead of sleeping between frames.
+fn vsync() -> bool {
+    std::env::args().any(|a| a == "--vsync")
+}
+
+/// Use the saved key bindings when there are some.
+fn load_bindings<B: backend::Backend>(game: &mut Game<B>) -> () {
+    let path = assets::asset_path(BINDINGS_FILE);
+    if !path.exists() {
+        return;
+    }
+    match assets::load_bindings(path) {
+        Ok(b) => *game.bindings.borrow_mut() = b,
+        Err(e) => warn!("Using default key bindings: {}", e)
+    }
+}
+
 fn main() {
     env_logger::init().unwrap();
 
     info!("Initializing game");
-    let mut game = Game::new().unwrap();
-    info!("Initialized");
+    let result = match headless_frames() {
+        Some(frames) => {
+            let mut game = Game::new(HeadlessBackend::new(Some(frames)));
+            info!("Initialized headless, running {} frames", frames);
+            load_bindings(&mut game);
+            test_scene(game.bindings.clone()).and_then(|scene| {
+                game.push_scene(Box::new(scene));
+                game.run()
+            })
+        },
+        None => {
+            let mut game = Game::new(SdlBackend::new("gbjam4", vsync()).unwrap());
+            info!("Initialized");
+            load_bindings(&mut game);
+
+            info!("Running");
+            test_scene(game.bindings.clone()).and_then(|scene| {
+                game.push_scene(Box::new(scene));
+                game.run()
+            })
+        }
+    };
 
-    info!("Running");
-    match game.run() {
+    match result {
         Ok(_) => info!("Exited successfully"),
         Err(s) => error!("Exited abnormally: {}", s)
     }
@@ -1,12 +1,13 @@
 use std::ops::Add;
 use std::ops::Neg;
 use std::ops::Sub;
+use std::ops::Mul;
 use std::convert::Into;
 
 pub mod size;
 pub mod rect;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Position {
     pub x: i32,
     pub y: i32
@@ -57,7 +58,7 @@ impl Position {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Vector {
     pub x: f32,
     pub y: f32
@@ -93,6 +94,17 @@ impl Sub for Vector {
     }
 }
 
+impl Mul<f32> for Vector {
+    type Output = Vector;
+
+    fn mul(self, rhs: f32) -> Vector {
+        Vector {
+            x: self.x * rhs,
+            y: self.y * rhs
+        }
+    }
+}
+
 impl Into<Position> for Vector {
     fn into(self) -> Position {
         Position {
@@ -106,4 +118,23 @@ impl Vector {
     pub fn new(x: f32, y: f32) -> Vector {
         Vector { x: x, y: y }
     }
+
+    pub fn zero() -> Vector {
+        Vector { x: 0.0, y: 0.0 }
+    }
+
+    #[inline]
+    pub fn length(&self) -> f32 {
+        (self.x * self.x + self.y * self.y).sqrt()
+    }
+
+    /// Scale the vector down so its length is at most `max`.
+    pub fn clamp_length(self, max: f32) -> Vector {
+        let len = self.length();
+        if len > max && len > 0.0 {
+            self * (max / len)
+        } else {
+            self
+        }
+    }
 }
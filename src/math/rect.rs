@@ -1,7 +1,9 @@
 use super::size::Size;
 use super::Position;
 
-#[derive(Copy, Clone)]
+use std::cmp::{min, max};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Rect {
     position: Position,
     size: Size
@@ -21,32 +23,25 @@ impl Rect {
         }
     }
 
-    /// Clip a Rect into a bounds Rect.
+    /// Clip a Rect into a bounds Rect. The result is the intersection of the
+    /// two, or a zero Rect if they don't overlap.
     pub fn clip(&self, bounds: &Rect) -> Self {
-        let b_max_x = bounds.max_x();
-        let b_min_x = bounds.x();
-        let b_max_y = bounds.max_y();
-        let b_min_y = bounds.y();
+        let min_x = max(self.x(), bounds.x());
+        let min_y = max(self.y(), bounds.y());
+        let max_x = min(self.max_x(), bounds.max_x());
+        let max_y = min(self.max_y(), bounds.max_y());
 
-        if self.x() >= b_max_x || self.y() >= b_max_y {
-            return Self::zero();
-        }
-        if self.max_x() < b_min_x || self.max_y() < b_min_y {
+        if max_x <= min_x || max_y <= min_y {
             return Self::zero();
         }
 
-        let mut res = *self;
-
-        if self.max_x() >= b_max_x {
-            let xclip = self.max_x() - b_max_x;
-            res.size.width -= xclip as u32;
-        }
-        if self.max_y() >= b_max_y {
-            let yclip = self.max_y() - b_max_y;
-            res.size.height -= yclip as u32;
-        }
+        Self::new(min_x, min_y, (max_x - min_x) as u32, (max_y - min_y) as u32)
+    }
 
-        res
+    /// The same Rect moved by an offset.
+    #[inline]
+    pub fn offset(&self, x: i32, y: i32) -> Self {
+        Self::new(self.x() + x, self.y() + y, self.w(), self.h())
     }
 
     #[inline]
@@ -68,3 +63,74 @@ impl Rect {
         self.w() == 0 || self.h() == 0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Rect;
+
+    fn bounds() -> Rect {
+        Rect::new(0, 0, 160, 144)
+    }
+
+    #[test]
+    fn clip_inside() {
+        let r = Rect::new(10, 20, 16, 16);
+        assert_eq!(r.clip(&bounds()), r);
+    }
+
+    #[test]
+    fn clip_left() {
+        assert_eq!(Rect::new(-4, 10, 16, 16).clip(&bounds()), Rect::new(0, 10, 12, 16));
+    }
+
+    #[test]
+    fn clip_top() {
+        assert_eq!(Rect::new(10, -6, 16, 16).clip(&bounds()), Rect::new(10, 0, 16, 10));
+    }
+
+    #[test]
+    fn clip_right() {
+        assert_eq!(Rect::new(150, 10, 16, 16).clip(&bounds()), Rect::new(150, 10, 10, 16));
+    }
+
+    #[test]
+    fn clip_bottom() {
+        assert_eq!(Rect::new(10, 140, 16, 16).clip(&bounds()), Rect::new(10, 140, 16, 4));
+    }
+
+    #[test]
+    fn clip_top_left_corner() {
+        assert_eq!(Rect::new(-8, -8, 16, 16).clip(&bounds()), Rect::new(0, 0, 8, 8));
+    }
+
+    #[test]
+    fn clip_bottom_right_corner() {
+        assert_eq!(Rect::new(156, 140, 16, 16).clip(&bounds()), Rect::new(156, 140, 4, 4));
+    }
+
+    #[test]
+    fn clip_larger_than_bounds() {
+        assert_eq!(Rect::new(-10, -10, 200, 200).clip(&bounds()), bounds());
+    }
+
+    #[test]
+    fn clip_offset_bounds() {
+        let b = Rect::new(8, 8, 8, 8);
+        assert_eq!(Rect::new(0, 12, 32, 2).clip(&b), Rect::new(8, 12, 8, 2));
+    }
+
+    #[test]
+    fn clip_disjoint() {
+        assert!(Rect::new(-16, 10, 16, 16).clip(&bounds()).is_zero());
+        assert!(Rect::new(10, -16, 16, 16).clip(&bounds()).is_zero());
+        assert!(Rect::new(160, 10, 16, 16).clip(&bounds()).is_zero());
+        assert!(Rect::new(10, 144, 16, 16).clip(&bounds()).is_zero());
+        assert!(Rect::new(-100, -100, 16, 16).clip(&bounds()).is_zero());
+    }
+
+    #[test]
+    fn clip_zero_sized() {
+        assert!(Rect::new(10, 10, 0, 16).clip(&bounds()).is_zero());
+        assert!(Rect::new(10, 10, 16, 16).clip(&Rect::zero()).is_zero());
+    }
+}
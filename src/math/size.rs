@@ -1,7 +1,7 @@
 use std::ops::{Add, Sub};
 use std::convert::Into;
 
-#[derive(Copy, Clone, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Size {
     pub width: u32,
     pub height: u32
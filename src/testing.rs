@@ -0,0 +1,67 @@
+//! Fixtures shared by the unit tests.
+
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+use ::game::scene::{Scene, SceneChange};
+use ::gfx::screen::Screen;
+use ::gfx::image::Image;
+use ::input::{InputState, PressedState, Button};
+
+/// An image filled with one shade.
+pub fn solid(w: u32, h: u32, shade: u8) -> Rc<Image> {
+    Rc::new(Image::new((w, h), shade))
+}
+
+/// Input with `buttons` just pressed and every other button up.
+pub fn press(buttons: &[Button]) -> InputState {
+    let mut input = InputState::new();
+    for &b in buttons.iter() {
+        input.set(b, PressedState::Pressed);
+    }
+    input
+}
+
+pub type Log = Rc<RefCell<Vec<String>>>;
+
+pub fn log() -> Log {
+    Rc::new(RefCell::new(Vec::new()))
+}
+
+/// A scene that logs every call as `<name> <call>`, keeps the input of
+/// every update, and returns the scripted changes from `update` in turn.
+pub struct Recorder {
+    name: &'static str,
+    log: Log,
+    pub inputs: Rc<RefCell<Vec<InputState>>>,
+    script: VecDeque<SceneChange>
+}
+
+impl Recorder {
+    pub fn new(name: &'static str, log: &Log, script: Vec<SceneChange>) -> Box<Recorder> {
+        Box::new(Recorder {
+            name: name,
+            log: log.clone(),
+            inputs: Rc::new(RefCell::new(Vec::new())),
+            script: script.into_iter().collect()
+        })
+    }
+
+    fn note(&self, call: &str) -> () {
+        self.log.borrow_mut().push(format!("{} {}", self.name, call));
+    }
+}
+
+impl Scene for Recorder {
+    fn enter(&mut self, _screen: &Rc<RefCell<Screen>>) -> () { self.note("enter"); }
+    fn exit(&mut self, _screen: &Rc<RefCell<Screen>>) -> () { self.note("exit"); }
+    fn pause(&mut self, _screen: &Rc<RefCell<Screen>>) -> () { self.note("pause"); }
+    fn resume(&mut self, _screen: &Rc<RefCell<Screen>>) -> () { self.note("resume"); }
+
+    fn update(&mut self, input: InputState, _screen: &Rc<RefCell<Screen>>) -> SceneChange {
+        self.note("update");
+        self.inputs.borrow_mut().push(input);
+        self.script.pop_front().unwrap_or(SceneChange::Continue)
+    }
+}